@@ -0,0 +1,252 @@
+//! floods a server with clients and reports how long messages took to arrive and how many never did
+//!
+//! `cargo run --release --example flood -- --clients 500 --senders 50 --rate 20`
+//! runs against a server of its own, give `--addr` to flood one that's already running
+//!
+//! running it again with `--worker-threads 4` compares the server's single threaded runtime
+//! against the multi threaded one, like the server binary's flag of the same name
+//!
+//! `--slow-clients 1 --padding 4000` adds a client that stops reading, showing what one stalled peer
+//! costs everyone else under each `--backpressure` policy, the padding gets past what the kernel buffers
+
+use std::{
+    io,
+    net::SocketAddr,
+    num::NonZeroUsize,
+    thread,
+    time::{Duration, Instant},
+};
+
+use broadcast_server_example::{
+    config::{BackpressurePolicy, ServerConfig},
+    server::{Message, Server},
+    test_util::TestClient,
+};
+use clap::Parser;
+use futures::future;
+use tokio::{
+    net::TcpSocket,
+    runtime,
+    sync::{mpsc, oneshot},
+};
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// server to flood, one is started in process on an ephemeral port when unset
+    #[arg(long)]
+    addr: Option<SocketAddr>,
+
+    /// what the in process server does once a client falls behind
+    #[arg(long, value_enum, default_value_t = BackpressurePolicy::default())]
+    backpressure: BackpressurePolicy,
+
+    /// worker threads for the in process server's writers, it runs on a thread of its own when unset
+    #[arg(long)]
+    worker_threads: Option<NonZeroUsize>,
+
+    /// clients connected at once, every one of them reads everything it's sent
+    #[arg(long, default_value_t = 100)]
+    clients: usize,
+
+    /// clients connected on top of `--clients` that never read a thing, with a small receive buffer
+    /// so they fall behind straight away, they're left out of the report
+    #[arg(long, default_value_t = 0)]
+    slow_clients: usize,
+
+    /// how many of the clients also send
+    #[arg(long, default_value_t = 10)]
+    senders: usize,
+
+    /// messages per second each sender sends
+    #[arg(long, default_value_t = 10.0, value_parser = rate)]
+    rate: f64,
+
+    /// bytes of filler added to every message, bigger messages fill a slow client's buffers sooner
+    #[arg(long, default_value_t = 0)]
+    padding: usize,
+
+    /// seconds spent sending
+    #[arg(long, default_value_t = 5)]
+    duration: u64,
+
+    /// seconds the clients keep reading after the last message was sent
+    #[arg(long, default_value_t = 1)]
+    drain: u64,
+}
+
+/// a rate, anything that doesn't leave a period between messages is refused
+fn rate(arg: &str) -> Result<f64, String> {
+    let rate = arg.parse::<f64>().map_err(|e| e.to_string())?;
+    // the ticker panics on a zero period
+    if Duration::try_from_secs_f64(1.0 / rate).is_ok_and(|period| !period.is_zero()) {
+        Ok(rate)
+    } else {
+        Err("must be a positive, finite number".to_owned())
+    }
+}
+
+/// what a single client saw
+#[derive(Debug, Default)]
+struct Report {
+    sent: u64,
+    /// from the moment each flood message was sent until it arrived
+    latencies: Vec<Duration>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> io::Result<()> {
+    let args = Args::parse();
+
+    let addr = match args.addr {
+        Some(addr) => addr,
+        None => spawn_server(&args).await?,
+    };
+
+    // held open until the flood is over, without ever being read from
+    let mut slow = Vec::with_capacity(args.slow_clients);
+    for _ in 0..args.slow_clients {
+        slow.push(connect_slow(addr).await?);
+    }
+
+    let reports = flood(addr, &args).await?;
+    drop(slow);
+
+    let sent: u64 = reports.iter().map(|report| report.sent).sum();
+    let mut latencies: Vec<_> = reports.into_iter().flat_map(|r| r.latencies).collect();
+    latencies.sort_unstable();
+
+    // every client but the sender should get each message
+    let expected = sent * (args.clients as u64).saturating_sub(1);
+    let received = latencies.len() as u64;
+    let percentile = |p: usize| {
+        latencies
+            .get((latencies.len() * p / 100).min(latencies.len().saturating_sub(1)))
+            .copied()
+            .unwrap_or_default()
+    };
+
+    println!(
+        "sent {sent} messages to {} clients and {} slow ones",
+        args.clients, args.slow_clients
+    );
+    println!(
+        "received {received} of {expected} ({:.2}%), {} dropped",
+        received as f64 * 100.0 / expected.max(1) as f64,
+        expected.saturating_sub(received)
+    );
+    println!("p50 {:?}, p99 {:?}", percentile(50), percentile(99));
+
+    Ok(())
+}
+
+/// runs a server on a runtime of its own, so the clients flooding it don't take turns with it
+async fn spawn_server(args: &Args) -> io::Result<SocketAddr> {
+    let config = ServerConfig::builder()
+        .bind(["127.0.0.1:0".parse().unwrap()])
+        .backpressure(args.backpressure)
+        .build();
+    let runtime = match args.worker_threads {
+        Some(threads) => runtime::Builder::new_multi_thread()
+            .worker_threads(threads.get())
+            .enable_all()
+            .build()?,
+        None => runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?,
+    };
+
+    let (bound, addr) = oneshot::channel();
+    thread::spawn(move || {
+        runtime.block_on(async move {
+            let server = match Server::bind(config).await {
+                Ok(server) => server,
+                Err(e) => {
+                    let _ = bound.send(Err(io::Error::other(e)));
+                    return;
+                }
+            };
+            let addr = server
+                .local_addr()
+                .expect("a tcp listener to have an address");
+            let _ = bound.send(Ok(addr));
+
+            let (_, announcements) = mpsc::channel(1);
+            let _ = server.run(announcements, std::future::pending()).await;
+        })
+    });
+
+    addr.await.map_err(io::Error::other)?
+}
+
+/// a client that can only take in a few messages before the server has to queue for it
+async fn connect_slow(addr: SocketAddr) -> io::Result<TestClient> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.set_recv_buffer_size(4096)?;
+    TestClient::from_stream(socket.connect(addr).await?).await
+}
+
+async fn flood(addr: SocketAddr, args: &Args) -> io::Result<Vec<Report>> {
+    let mut clients = Vec::with_capacity(args.clients);
+    for _ in 0..args.clients {
+        clients.push(TestClient::connect(addr).await?);
+    }
+
+    // a shared clock, each message carries when it was sent in microseconds since then
+    let started = Instant::now();
+    let sending_until = started + Duration::from_secs(args.duration);
+    let reading_until = sending_until + Duration::from_secs(args.drain);
+    let period = Duration::from_secs_f64(1.0 / args.rate);
+    let padding = "x".repeat(args.padding);
+    let padding = &padding;
+
+    let runs = clients
+        .into_iter()
+        .enumerate()
+        .map(|(i, mut client)| async move {
+            let mut report = Report::default();
+            let mut ticker = tokio::time::interval(period);
+            let sender = i < args.senders;
+
+            loop {
+                let now = Instant::now();
+                if now >= reading_until {
+                    break;
+                }
+                let sending = sender && now < sending_until;
+
+                tokio::select! {
+                    _ = ticker.tick(), if sending => {
+                        let micros = started.elapsed().as_micros();
+                        client.send_line(&format!("flood {micros} {padding}")).await?;
+                        report.sent += 1;
+                    }
+                    line = client.recv_line() => {
+                        let Some(line) = line? else {
+                            break;
+                        };
+                        let Ok(Message::Chat { content, .. }) = line.parse() else {
+                            continue;
+                        };
+                        let Some(micros) = content
+                            .strip_prefix("flood ")
+                            .and_then(|rest| rest.split(' ').next())
+                            .and_then(|micros| micros.parse().ok())
+                        else {
+                            continue;
+                        };
+
+                        let sent_at = started + Duration::from_micros(micros);
+                        report.latencies.push(sent_at.elapsed());
+                    }
+                    _ = tokio::time::sleep_until(reading_until.into()) => {}
+                }
+            }
+
+            io::Result::Ok(report)
+        });
+
+    future::try_join_all(runs).await
+}
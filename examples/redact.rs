@@ -0,0 +1,31 @@
+//! a broadcast server that censors a banned word before relaying messages
+
+use broadcast_server_example::{
+    config::{Filtered, ServerConfig},
+    server::{serve, ServeError},
+};
+use tokio::sync::mpsc;
+
+const BANNED: &str = "heck";
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), ServeError> {
+    tracing_subscriber::fmt::init();
+
+    let config = ServerConfig::builder()
+        .filter(|msg| {
+            if msg.contains(BANNED) {
+                Filtered::Rewrite(msg.replace(BANNED, &"*".repeat(BANNED.len())))
+            } else {
+                Filtered::Pass
+            }
+        })
+        .build();
+
+    let (_, announcements) = mpsc::channel(1);
+
+    // the stats are logged on the way out as well
+    serve(config, announcements, std::future::pending()).await?;
+
+    Ok(())
+}
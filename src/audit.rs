@@ -0,0 +1,98 @@
+use std::{fmt, io, path::Path, time::Duration};
+
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncWriteExt, BufWriter},
+    select,
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinHandle,
+};
+use tracing::warn;
+
+/// how many records may wait on the disk before new ones are dropped
+const QUEUE_LEN: usize = 1024;
+
+/// how often buffered records are flushed to disk
+const FLUSH_PERIOD: Duration = Duration::from_secs(1);
+
+/// an append only record of every broadcast message
+///
+/// written from its own task so a slow disk never holds up the event loop
+pub(crate) struct AuditLog {
+    tx: mpsc::Sender<String>,
+    writer: JoinHandle<()>,
+}
+
+impl AuditLog {
+    /// opens `path` for appending, creating it if needed but never truncating it
+    pub(crate) async fn open(path: &Path) -> io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+    }
+
+    pub(crate) fn spawn(file: File) -> Self {
+        let (tx, mut rx) = mpsc::channel::<String>(QUEUE_LEN);
+
+        let writer = tokio::spawn(async move {
+            let mut file = BufWriter::new(file);
+            let mut flush = tokio::time::interval(FLUSH_PERIOD);
+
+            loop {
+                select! {
+                    record = rx.recv() => {
+                        // the sender is only dropped once the server stops
+                        let Some(record) = record else {
+                            break;
+                        };
+
+                        if let Err(e) = file.write_all(record.as_bytes()).await {
+                            warn!("error writing to the audit log: {e}");
+                        }
+                    }
+                    _ = flush.tick() => {
+                        if let Err(e) = file.flush().await {
+                            warn!("error flushing the audit log: {e}");
+                        }
+                    }
+                }
+            }
+
+            if let Err(e) = file.flush().await {
+                warn!("error flushing the audit log: {e}");
+            }
+        });
+
+        AuditLog { tx, writer }
+    }
+
+    /// queues a `<room> <seq> <sent_at> <id> <content>` line, sequence numbers only count within the room,
+    /// content is escaped so a record never spans lines
+    ///
+    /// `from` is a client id, or the address a datagram came from
+    pub(crate) fn record(
+        &self,
+        room: &str,
+        seq: u64,
+        from: impl fmt::Display,
+        sent_at: u128,
+        content: &[u8],
+    ) {
+        let content = String::from_utf8_lossy(content);
+        let record = format!("{room} {seq} {sent_at} {from} {}\n", content.escape_debug());
+
+        match self.tx.try_send(record) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => warn!("audit log is falling behind, dropping a record"),
+            Err(TrySendError::Closed(_)) => warn!("audit log writer has stopped"),
+        }
+    }
+
+    /// waits for everything queued to be written out
+    pub(crate) async fn close(self) {
+        drop(self.tx);
+        let _ = self.writer.await;
+    }
+}
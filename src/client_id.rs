@@ -0,0 +1,48 @@
+use std::{fmt, net::SocketAddr, sync::Arc};
+
+use serde::Deserialize;
+
+/// how clients are numbered as they're accepted
+///
+/// only `Monotonic` guarantees every client a number of its own, with the others
+/// a client that would share its id with one still connected is turned away with `ERR:id in use`
+#[derive(Debug, Clone, Default, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClientIds {
+    /// counts up from 0, never reusing an id
+    #[default]
+    Monotonic,
+    /// the peer's port, unix socket peers are counted up from 0 instead
+    Port,
+    /// whatever the function makes of the peer address, `None` for unix socket peers,
+    /// and of the token the client authenticated with, `None` without `auth_token`
+    #[value(skip)]
+    #[serde(skip)]
+    Custom(DeriveId),
+}
+
+/// picks the id for a client from its address and token, see [`ClientIds::Custom`]
+///
+/// called once the client is through any tls handshake and authentication
+#[derive(Clone)]
+pub struct DeriveId(Arc<DeriveFn>);
+
+type DeriveFn = dyn Fn(Option<SocketAddr>, Option<&str>) -> u64 + Send + Sync;
+
+impl DeriveId {
+    pub fn new(
+        derive: impl Fn(Option<SocketAddr>, Option<&str>) -> u64 + Send + Sync + 'static,
+    ) -> Self {
+        DeriveId(Arc::new(derive))
+    }
+
+    pub(crate) fn apply(&self, addr: Option<SocketAddr>, token: Option<&str>) -> u64 {
+        (self.0)(addr, token)
+    }
+}
+
+impl fmt::Debug for DeriveId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DeriveId(..)")
+    }
+}
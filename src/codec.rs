@@ -0,0 +1,213 @@
+use std::io::{self, Write};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use flate2::{write::DeflateEncoder, Compression};
+use serde::Deserialize;
+use thiserror::Error;
+use tokio_util::codec::{
+    AnyDelimiterCodec, AnyDelimiterCodecError, Decoder, Encoder, LengthDelimitedCodec, LinesCodec,
+    LinesCodecError,
+};
+
+/// how frames are delimited on the wire
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Framing {
+    /// newline terminated utf-8 lines, a client sending anything else is disconnected
+    ///
+    /// with the default delimiter [`Framing::Delimited`] reads the same lines as raw bytes instead
+    #[default]
+    Lines,
+    /// a 4 byte big endian length followed by that many arbitrary bytes
+    LengthDelimited,
+    /// arbitrary bytes ended by any of the configured delimiter bytes, relayed as they are
+    Delimited,
+}
+
+#[derive(Error, Debug)]
+pub enum FrameError {
+    /// unlike the others the connection is still usable afterwards,
+    /// the rest of the frame is skipped
+    #[error("frame too long")]
+    TooLong,
+    #[error(transparent)]
+    Lines(#[from] LinesCodecError),
+    #[error(transparent)]
+    Delimited(#[from] AnyDelimiterCodecError),
+    #[error(transparent)]
+    WebSocket(#[from] tokio_tungstenite::tungstenite::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// a decoded frame, oversized ones are reported rather than failing the stream
+/// since FramedRead gives up after the first decoding error
+#[derive(Debug)]
+pub(crate) enum Frame {
+    Data(Bytes),
+    TooLong,
+}
+
+impl From<Frame> for Result<Bytes, FrameError> {
+    fn from(frame: Frame) -> Self {
+        match frame {
+            Frame::Data(bytes) => Ok(bytes),
+            Frame::TooLong => Err(FrameError::TooLong),
+        }
+    }
+}
+
+/// a frame on its way out, either as is or deflated for a client that asked for compression
+#[derive(Debug, Clone)]
+pub(crate) enum OutFrame {
+    Plain(Bytes),
+    /// sent as a 4 byte big endian length followed by the raw deflate stream,
+    /// whatever the framing, since compressed bytes may contain any delimiter
+    Deflated(Bytes),
+}
+
+impl OutFrame {
+    /// bytes written before framing
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            OutFrame::Plain(bytes) | OutFrame::Deflated(bytes) => bytes.len(),
+        }
+    }
+
+    pub(crate) fn deflate(plain: &[u8]) -> Self {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+
+        // writing to a vec never fails
+        let _ = encoder.write_all(plain);
+        OutFrame::Deflated(encoder.finish().unwrap_or_default().into())
+    }
+}
+
+/// either codec behind a single type so connections don't need to be generic over it
+#[derive(Debug, Clone)]
+pub(crate) enum FrameCodec {
+    Lines(LinesCodec),
+    LengthDelimited(LengthDelimitedCodec),
+    Delimited {
+        codec: AnyDelimiterCodec,
+        /// what outgoing frames are ended with
+        delimiter: u8,
+    },
+}
+
+impl FrameCodec {
+    /// `max_length` caps a line or a frame's payload in bytes,
+    /// `delimiters` only matter for [`Framing::Delimited`] and must not be empty
+    pub(crate) fn new(framing: Framing, delimiters: &[u8], max_length: usize) -> Self {
+        match framing {
+            Framing::Lines => FrameCodec::Lines(LinesCodec::new_with_max_length(max_length)),
+            Framing::LengthDelimited => FrameCodec::LengthDelimited(
+                LengthDelimitedCodec::builder()
+                    .max_frame_length(max_length)
+                    .new_codec(),
+            ),
+            Framing::Delimited => FrameCodec::Delimited {
+                codec: AnyDelimiterCodec::new_with_max_length(
+                    delimiters.to_vec(),
+                    delimiters[..1].to_vec(),
+                    max_length,
+                ),
+                delimiter: delimiters[0],
+            },
+        }
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Frame;
+    type Error = FrameError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let res = match self {
+            FrameCodec::Lines(codec) => codec
+                .decode(src)
+                .map(|line| line.map(Bytes::from))
+                .map_err(Into::into),
+            FrameCodec::LengthDelimited(codec) => {
+                return Ok(codec.decode(src)?.map(|frame| Frame::Data(frame.freeze())))
+            }
+            FrameCodec::Delimited { codec, .. } => codec.decode(src).map_err(Into::into),
+        };
+
+        recover_too_long(res)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let res = match self {
+            FrameCodec::Lines(codec) => codec
+                .decode_eof(src)
+                .map(|line| line.map(Bytes::from))
+                .map_err(Into::into),
+            FrameCodec::LengthDelimited(codec) => {
+                return Ok(codec
+                    .decode_eof(src)?
+                    .map(|frame| Frame::Data(frame.freeze())))
+            }
+            FrameCodec::Delimited { codec, .. } => codec.decode_eof(src).map_err(Into::into),
+        };
+
+        recover_too_long(res)
+    }
+}
+
+/// both codecs discard the rest of an oversized frame on their own,
+/// so running into the limit doesn't need to end the stream
+///
+/// length delimited frames can't be skipped like that, their length errors stay fatal
+fn recover_too_long(res: Result<Option<Bytes>, FrameError>) -> Result<Option<Frame>, FrameError> {
+    match res {
+        Ok(frame) => Ok(frame.map(Frame::Data)),
+        Err(FrameError::Lines(LinesCodecError::MaxLineLengthExceeded))
+        | Err(FrameError::Delimited(AnyDelimiterCodecError::MaxChunkLengthExceeded)) => {
+            Ok(Some(Frame::TooLong))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+impl Encoder<OutFrame> for FrameCodec {
+    type Error = FrameError;
+
+    fn encode(&mut self, item: OutFrame, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let item = match item {
+            OutFrame::Plain(item) => item,
+            OutFrame::Deflated(item) => return put_length_prefixed(&item, dst),
+        };
+
+        match self {
+            FrameCodec::Lines(codec) => {
+                // everything sent in lines mode started out as a line, so this only
+                // fails if a frame that was never valid text made it here somehow
+                let line = std::str::from_utf8(&item)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Ok(codec.encode(line, dst)?)
+            }
+            // the limit is for what clients send, the header added to relayed
+            // messages could otherwise push a frame that was accepted over it
+            FrameCodec::LengthDelimited(_) => put_length_prefixed(&item, dst),
+            // AnyDelimiterCodec only encodes text, frames here may be arbitrary bytes
+            FrameCodec::Delimited { delimiter, .. } => {
+                dst.reserve(item.len() + 1);
+                dst.extend_from_slice(&item);
+                dst.extend_from_slice(&[*delimiter]);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// the same 4 byte big endian length LengthDelimitedCodec reads, without its size limit
+fn put_length_prefixed(item: &[u8], dst: &mut BytesMut) -> Result<(), FrameError> {
+    let len =
+        u32::try_from(item.len()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    dst.reserve(item.len() + 4);
+    dst.put_u32(len);
+    dst.extend_from_slice(item);
+    Ok(())
+}
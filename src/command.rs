@@ -0,0 +1,94 @@
+/// a line sent by a client that the server acts on instead of broadcasting
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command<'a> {
+    /// `/nick <name>`
+    Nick(&'a str),
+    /// `/list`
+    List,
+    /// `/list queues`, like `/list` with how far behind each peer is, e.g. `8001 q=12 buf=4096`
+    ListQueues,
+    /// `/stats`
+    Stats,
+    /// `/msg <id> <text>`
+    Msg { to: &'a str, text: &'a str },
+    /// `/msg-tag <tag> <text>`, a message for everyone in the room with that tag
+    MsgTag { tag: &'a str, text: &'a str },
+    /// `/ack-send <text>`, relayed like any message, then the sender is told how many peers it was queued for
+    AckSend(&'a str),
+    /// `/join <room>`
+    Join(&'a str),
+    /// `/leave`
+    Leave,
+    /// `/quit`
+    Quit,
+    /// `/whoami`
+    Whoami,
+    /// `/kick <id>`, only for admins
+    Kick(&'a str),
+    /// `COMPRESS on` or `COMPRESS off`, whether what the server sends is deflated
+    Compress(bool),
+    /// `MAXLEN <bytes>`, the longest line the client means to send
+    MaxLen(&'a str),
+    /// `TAG <label>`, what kind of client this is, e.g. `bot`
+    Tag(&'a str),
+    /// `MODE observer`, only receive from now on, e.g. for dashboards
+    Mode(&'a str),
+    /// `HELLO v<version> [feature]...`, which protocol version and optional features the client speaks
+    Hello { version: &'a str, features: &'a str },
+}
+
+impl<'a> Command<'a> {
+    /// returns `None` if the line isn't a known command and should be broadcast as is
+    pub fn parse(line: &'a str) -> Option<Self> {
+        // negotiated like `AUTH` rather than being a chat command
+        match line.strip_prefix("COMPRESS ") {
+            Some("on") => return Some(Command::Compress(true)),
+            Some("off") => return Some(Command::Compress(false)),
+            _ => {}
+        }
+
+        if let Some(max_len) = line.strip_prefix("MAXLEN ") {
+            return Some(Command::MaxLen(max_len.trim()));
+        }
+
+        if let Some(tag) = line.strip_prefix("TAG ") {
+            return Some(Command::Tag(tag.trim()));
+        }
+
+        if let Some(mode) = line.strip_prefix("MODE ") {
+            return Some(Command::Mode(mode.trim()));
+        }
+
+        if let Some(hello) = line.strip_prefix("HELLO ") {
+            let hello = hello.trim();
+            let (version, features) = hello.split_once(' ').unwrap_or((hello, ""));
+            return Some(Command::Hello { version, features });
+        }
+
+        let line = line.strip_prefix('/')?;
+        let (verb, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = rest.trim();
+
+        match verb.to_ascii_lowercase().as_str() {
+            "nick" => Some(Command::Nick(rest)),
+            "list" if rest == "queues" => Some(Command::ListQueues),
+            "list" => Some(Command::List),
+            "stats" => Some(Command::Stats),
+            "msg" => {
+                let (to, text) = rest.split_once(' ').unwrap_or((rest, ""));
+                Some(Command::Msg { to, text })
+            }
+            "msg-tag" => {
+                let (tag, text) = rest.split_once(' ').unwrap_or((rest, ""));
+                Some(Command::MsgTag { tag, text })
+            }
+            "join" => Some(Command::Join(rest)),
+            "leave" => Some(Command::Leave),
+            "quit" => Some(Command::Quit),
+            "whoami" => Some(Command::Whoami),
+            "kick" => Some(Command::Kick(rest)),
+            "ack-send" => Some(Command::AckSend(rest)),
+            _ => None,
+        }
+    }
+}
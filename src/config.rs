@@ -0,0 +1,688 @@
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use ipnet::IpNet;
+use serde::{Deserialize, Deserializer};
+use thiserror::Error;
+
+pub use crate::{
+    client_id::{ClientIds, DeriveId},
+    codec::Framing,
+    filter::{Filtered, MessageFilter},
+    protocol::Protocol,
+};
+
+/// longest line a client may send unless configured otherwise
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 8192;
+
+/// how long a client may stay silent unless configured otherwise
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// how long writing a single frame may take unless configured otherwise
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// how many messages may wait to be written to a single client unless configured otherwise
+pub const DEFAULT_OUTBOUND_QUEUE_LEN: usize = 64;
+
+/// how many clients a broadcast reaches before giving other tasks a turn unless configured otherwise
+pub const DEFAULT_BROADCAST_YIELD_EVERY: usize = 64;
+
+/// what ends a frame with [`Framing::Delimited`] unless configured otherwise
+pub const DEFAULT_DELIMITERS: &[u8] = b"\n";
+
+/// sends `PING` every `interval` and drops clients that miss `max_missed` `PONG`s in a row
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keepalive {
+    /// seconds in a config file
+    #[serde(deserialize_with = "secs")]
+    pub interval: Duration,
+    pub max_missed: u32,
+}
+
+/// `SO_KEEPALIVE` probes, so the kernel notices peers that vanished without a word
+/// even while nothing is being sent to them
+///
+/// a dead peer is found out after `time` plus `retries` times `interval`,
+/// then reading from it fails and it's disconnected like any other read error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TcpKeepalive {
+    /// how long a connection may sit idle before the first probe, seconds in a config file
+    #[serde(deserialize_with = "secs")]
+    pub time: Duration,
+    /// between probes that go unanswered, seconds in a config file
+    #[serde(deserialize_with = "secs")]
+    pub interval: Duration,
+    /// unanswered probes before the connection is given up on
+    pub retries: u32,
+}
+
+/// how long a client gets to authenticate unless configured otherwise
+pub const DEFAULT_AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// how long an empty room's history is kept unless configured otherwise
+pub const DEFAULT_HISTORY_TTL: Duration = Duration::from_secs(300);
+
+/// how long draining on SIGTERM may take unless configured otherwise
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// how long shutting down may take unless configured otherwise
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// lets each client send `burst` messages at once, refilled at `per_second`
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+/// drops a message identical to one of the client's last `cache_size` if it comes within `window` of it
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dedup {
+    /// seconds in a config file
+    #[serde(deserialize_with = "secs")]
+    pub window: Duration,
+    pub cache_size: usize,
+}
+
+/// pem encoded certificate chain and private key to encrypt connections with
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// what starts the plain text lines that aren't fixed, each including its separator
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Prefixes {
+    /// `LOGIN:` by default
+    pub login: String,
+    /// `MESSAGE:` by default
+    pub message: String,
+}
+
+impl Default for Prefixes {
+    fn default() -> Self {
+        Prefixes {
+            login: "LOGIN:".to_owned(),
+            message: "MESSAGE:".to_owned(),
+        }
+    }
+}
+
+/// what happens when a client's outbound queue is full
+///
+/// only `Block` ever loses nothing, but it does so by holding up every other client too,
+/// the drop policies keep everyone else moving at the cost of gaps for the slow client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BackpressurePolicy {
+    /// the server waits for the queue to drain,
+    /// so the slowest client sets the pace for everyone
+    Block,
+    /// the message is dropped for that client only
+    DropNewest,
+    /// the oldest queued message is dropped for that client to make room,
+    /// so what it does get is as recent as possible
+    DropOldest,
+    /// the client is disconnected
+    #[default]
+    Disconnect,
+}
+
+/// every field may be given in a toml file read with [`ServerConfig::from_file`]
+/// except `filter` and custom client ids, durations are in seconds and enums are spelled like the command line flags
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// socket addresses to serve requests from, clients on any of them share one server
+    pub bind: Vec<SocketAddr>,
+    /// lets ipv4 clients connect to ipv6 `bind` addresses as well, every address must be ipv6
+    pub dual_stack: bool,
+    /// tcp clients are taken to be behind a load balancer that starts every connection
+    /// with a PROXY protocol v1 header, whose address stands in for the balancer's own
+    pub proxy_protocol: bool,
+    /// listens on this unix socket path instead of `bind` when set
+    pub unix_socket: Option<PathBuf>,
+    /// also takes datagrams here, each broadcast to the default room as a message from its source address,
+    /// best effort with no `LOGIN` and nothing retried, disabled when `None`
+    ///
+    /// senders are held to `allow`, `deny` and the same limits as a connection, keyed by address
+    pub udp_bind: Option<SocketAddr>,
+    /// datagrams are also relayed to the udp peers heard from within this long, udp is receive only when `None`
+    #[serde(deserialize_with = "opt_secs")]
+    pub udp_peer_ttl: Option<Duration>,
+    /// longest line or frame in bytes any client may send, even after asking for more with `MAXLEN`
+    pub max_line_length: usize,
+    /// longest line or frame in bytes a client may send until it sends `MAXLEN <bytes>`,
+    /// `max_line_length` when `None`
+    pub default_max_line_length: Option<usize>,
+    /// longest chat message in bytes that's broadcast, usually well under `max_line_length`
+    /// so nobody can flood everyone with huge messages, only the frame length applies when `None`
+    pub max_message_bytes: Option<usize>,
+    /// most clients connected at once, unlimited when `None`
+    pub max_connections: Option<usize>,
+    /// most clients connected at once from a single ip, unlimited when `None`
+    pub max_connections_per_ip: Option<usize>,
+    /// how long after a connection from an ip is accepted that ip is turned away with `ERR:slow down`,
+    /// calms clients stuck reconnecting in a loop, anyone may reconnect right away when `None`
+    #[serde(deserialize_with = "opt_secs")]
+    pub reconnect_cooldown: Option<Duration>,
+    /// only these networks may connect, everyone may when empty
+    pub allow: Vec<IpNet>,
+    /// these networks may never connect, even if allowed
+    pub deny: Vec<IpNet>,
+    /// re-read on SIGHUP for new `allow` and `deny` lists, which replace the current ones whatever set them,
+    /// set by [`ServerConfig::from_file`], nothing is reloaded when `None`
+    #[serde(skip)]
+    pub config_file: Option<PathBuf>,
+    /// SIGHUP reloads `config_file` rather than being left to the host application,
+    /// the handler is process wide so only one server in a process should ask for it
+    #[serde(skip)]
+    pub reload_on_sighup: bool,
+    /// how long a client may stay silent before being disconnected
+    #[serde(deserialize_with = "secs")]
+    pub idle_timeout: Duration,
+    /// how long writing a single frame to a client may take before it's disconnected,
+    /// catches clients that stopped reading without closing the connection
+    #[serde(deserialize_with = "secs")]
+    pub write_timeout: Duration,
+    /// writes to a client taking longer than this are warned about, well before `write_timeout` gives up on it,
+    /// nothing is warned about when `None`
+    #[serde(deserialize_with = "opt_secs")]
+    pub slow_write_threshold: Option<Duration>,
+    /// disabled when `None`
+    pub keepalive: Option<Keepalive>,
+    /// sets `TCP_NODELAY` on every tcp connection so small lines go out right away
+    /// instead of waiting on nagle's algorithm
+    pub tcp_nodelay: bool,
+    /// the kernel's own keepalive probes on every tcp connection, left as the system default when `None`
+    pub tcp_keepalive: Option<TcpKeepalive>,
+    /// also send clients their own messages back
+    pub echo_self: bool,
+    /// send clients their own messages back and nobody else's,
+    /// for measuring round trips through the server with a single client
+    pub loopback: bool,
+    /// say why a client left, e.g. `LEFT:3 idle`
+    pub left_reason: bool,
+    /// follow the sender in `MESSAGE` with `#<n>`, counting each client's messages from 0,
+    /// so peers can tell they see them in the order they were sent
+    pub number_per_sender: bool,
+    /// how many recent messages are kept per room for clients that join it, none when 0
+    pub history_len: usize,
+    /// how long a room's history and numbering outlive its last client leaving,
+    /// forgotten right away when zero
+    #[serde(deserialize_with = "secs")]
+    pub history_ttl: Duration,
+    /// sent to every new client right after `LOGIN`, each line as a `MOTD:<line>` of its own
+    pub motd: Option<String>,
+    /// unlimited when `None`
+    pub rate_limit: Option<RateLimit>,
+    /// every message is relayed however often it's repeated when `None`
+    pub dedup: Option<Dedup>,
+    /// every message is broadcast as is when `None`
+    #[serde(skip)]
+    pub filter: Option<MessageFilter>,
+    /// how many messages may wait to be written to a single client
+    pub outbound_queue_len: usize,
+    /// what to do once a client's outbound queue fills up
+    pub backpressure: BackpressurePolicy,
+    /// `Custom` can't be given in a config file
+    pub client_ids: ClientIds,
+    /// most bytes waiting to be written across every client together,
+    /// messages are dropped for whoever they'd take over it, unlimited when `None`
+    pub max_queued_bytes: Option<usize>,
+    /// how many clients a broadcast reaches before letting other tasks run,
+    /// keeping ctrl-c and the writers responsive during big fan outs, never yields when 0
+    pub broadcast_yield_every: usize,
+    /// how lines are encoded on the wire
+    pub protocol: Protocol,
+    /// only used with [`Protocol::Plain`]
+    pub prefixes: Prefixes,
+    /// how frames are delimited on the wire
+    pub framing: Framing,
+    /// any of these bytes ends a frame with [`Framing::Delimited`],
+    /// outgoing frames are ended with the first one
+    pub delimiters: Vec<u8>,
+    /// plain tcp when `None`
+    pub tls: Option<Tls>,
+    /// speak websocket instead of raw frames, each message is one frame and `framing` is ignored
+    pub websocket: bool,
+    /// when set clients must send `AUTH <token>` before they join
+    pub auth_token: Option<String>,
+    /// clients that authenticate with this instead of `auth_token` may use admin commands like `/kick`,
+    /// needs `auth_token` to be set
+    pub admin_token: Option<String>,
+    /// how long a client gets to authenticate before being disconnected
+    #[serde(deserialize_with = "secs")]
+    pub auth_timeout: Duration,
+    /// how long a new plain tcp connection may take to send `HEALTH\n`, which is answered with
+    /// `OK\n` and closed without ever joining, for load balancer liveness checks,
+    /// clients that don't speak first are greeted that much later, no probes are recognised when `None`
+    #[serde(deserialize_with = "opt_secs")]
+    pub health_probe_window: Option<Duration>,
+    /// most clients in the middle of a tls handshake, websocket upgrade or authenticating at once,
+    /// further connections wait in the listen backlog, unlimited when `None`
+    pub max_handshakes: Option<usize>,
+    /// serves prometheus metrics over http at `/metrics`, disabled when `None`
+    pub metrics_addr: Option<SocketAddr>,
+    /// log messages per second and the number of connections every second
+    pub log_throughput: bool,
+    /// every broadcast message is appended to this file when set
+    pub log_file: Option<PathBuf>,
+    /// SIGTERM drains rather than being left to the host application,
+    /// the handler is process wide so only one server in a process should ask for it
+    #[serde(skip)]
+    pub drain_on_sigterm: bool,
+    /// how long clients may stay connected after SIGTERM before the server shuts down anyway
+    #[serde(deserialize_with = "secs")]
+    pub drain_timeout: Duration,
+    /// how long clients get to receive the goodbye once shutting down before they're cut off
+    #[serde(deserialize_with = "secs")]
+    pub shutdown_timeout: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: vec![SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::UNSPECIFIED,
+                8888,
+            ))],
+            dual_stack: false,
+            proxy_protocol: false,
+            unix_socket: None,
+            udp_bind: None,
+            udp_peer_ttl: None,
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+            default_max_line_length: None,
+            max_message_bytes: None,
+            max_connections: None,
+            max_connections_per_ip: None,
+            reconnect_cooldown: None,
+            allow: Vec::new(),
+            deny: Vec::new(),
+            config_file: None,
+            reload_on_sighup: false,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+            slow_write_threshold: None,
+            keepalive: None,
+            tcp_nodelay: true,
+            tcp_keepalive: None,
+            echo_self: false,
+            loopback: false,
+            left_reason: false,
+            number_per_sender: false,
+            history_len: 0,
+            history_ttl: DEFAULT_HISTORY_TTL,
+            motd: None,
+            rate_limit: None,
+            dedup: None,
+            filter: None,
+            outbound_queue_len: DEFAULT_OUTBOUND_QUEUE_LEN,
+            backpressure: BackpressurePolicy::default(),
+            client_ids: ClientIds::default(),
+            max_queued_bytes: None,
+            broadcast_yield_every: DEFAULT_BROADCAST_YIELD_EVERY,
+            protocol: Protocol::default(),
+            prefixes: Prefixes::default(),
+            framing: Framing::default(),
+            delimiters: DEFAULT_DELIMITERS.to_vec(),
+            tls: None,
+            websocket: false,
+            auth_token: None,
+            admin_token: None,
+            auth_timeout: DEFAULT_AUTH_TIMEOUT,
+            health_probe_window: None,
+            max_handshakes: None,
+            metrics_addr: None,
+            log_throughput: false,
+            log_file: None,
+            drain_on_sigterm: false,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+        }
+    }
+}
+
+/// durations in config files are seconds, like on the command line, fractions are allowed
+fn secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let secs = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+}
+
+fn opt_secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    Option::<f64>::deserialize(deserializer)?
+        .map(|secs| Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// why a config file couldn't be used
+#[derive(Error, Debug)]
+pub enum ConfigFileError {
+    #[error("couldn't read {}: {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("invalid config in {}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+}
+
+impl ServerConfig {
+    pub fn builder() -> ServerConfigBuilder {
+        ServerConfigBuilder::default()
+    }
+
+    /// reads a toml file, anything it leaves out keeps its default
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigFileError> {
+        let path = path.as_ref();
+
+        let contents = std::fs::read_to_string(path).map_err(|source| ConfigFileError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+
+        let mut config: ServerConfig =
+            toml::from_str(&contents).map_err(|source| ConfigFileError::Parse {
+                path: path.to_owned(),
+                source,
+            })?;
+        config.config_file = Some(path.to_owned());
+
+        Ok(config)
+    }
+}
+
+/// starts from [`ServerConfig::default`], every setter overrides the field of the same name
+#[derive(Debug, Clone, Default)]
+#[must_use]
+pub struct ServerConfigBuilder {
+    config: ServerConfig,
+}
+
+impl ServerConfigBuilder {
+    pub fn bind(mut self, bind: impl IntoIterator<Item = SocketAddr>) -> Self {
+        self.config.bind = bind.into_iter().collect();
+        self
+    }
+
+    pub fn dual_stack(mut self, dual_stack: bool) -> Self {
+        self.config.dual_stack = dual_stack;
+        self
+    }
+
+    pub fn proxy_protocol(mut self, proxy_protocol: bool) -> Self {
+        self.config.proxy_protocol = proxy_protocol;
+        self
+    }
+
+    pub fn unix_socket(mut self, unix_socket: impl Into<PathBuf>) -> Self {
+        self.config.unix_socket = Some(unix_socket.into());
+        self
+    }
+
+    pub fn udp_bind(mut self, udp_bind: SocketAddr) -> Self {
+        self.config.udp_bind = Some(udp_bind);
+        self
+    }
+
+    pub fn udp_peer_ttl(mut self, udp_peer_ttl: Duration) -> Self {
+        self.config.udp_peer_ttl = Some(udp_peer_ttl);
+        self
+    }
+
+    pub fn max_line_length(mut self, max_line_length: usize) -> Self {
+        self.config.max_line_length = max_line_length;
+        self
+    }
+
+    pub fn default_max_line_length(mut self, default_max_line_length: usize) -> Self {
+        self.config.default_max_line_length = Some(default_max_line_length);
+        self
+    }
+
+    pub fn max_message_bytes(mut self, max_message_bytes: usize) -> Self {
+        self.config.max_message_bytes = Some(max_message_bytes);
+        self
+    }
+
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.config.max_connections = Some(max_connections);
+        self
+    }
+
+    pub fn max_connections_per_ip(mut self, max_connections_per_ip: usize) -> Self {
+        self.config.max_connections_per_ip = Some(max_connections_per_ip);
+        self
+    }
+
+    pub fn reconnect_cooldown(mut self, reconnect_cooldown: Duration) -> Self {
+        self.config.reconnect_cooldown = Some(reconnect_cooldown);
+        self
+    }
+
+    pub fn allow(mut self, allow: impl IntoIterator<Item = IpNet>) -> Self {
+        self.config.allow = allow.into_iter().collect();
+        self
+    }
+
+    pub fn deny(mut self, deny: impl IntoIterator<Item = IpNet>) -> Self {
+        self.config.deny = deny.into_iter().collect();
+        self
+    }
+
+    pub fn config_file(mut self, config_file: impl Into<PathBuf>) -> Self {
+        self.config.config_file = Some(config_file.into());
+        self
+    }
+
+    pub fn reload_on_sighup(mut self, reload_on_sighup: bool) -> Self {
+        self.config.reload_on_sighup = reload_on_sighup;
+        self
+    }
+
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.config.idle_timeout = idle_timeout;
+        self
+    }
+
+    pub fn write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.config.write_timeout = write_timeout;
+        self
+    }
+
+    pub fn slow_write_threshold(mut self, slow_write_threshold: Duration) -> Self {
+        self.config.slow_write_threshold = Some(slow_write_threshold);
+        self
+    }
+
+    pub fn keepalive(mut self, keepalive: Keepalive) -> Self {
+        self.config.keepalive = Some(keepalive);
+        self
+    }
+
+    pub fn tcp_nodelay(mut self, tcp_nodelay: bool) -> Self {
+        self.config.tcp_nodelay = tcp_nodelay;
+        self
+    }
+
+    pub fn tcp_keepalive(mut self, tcp_keepalive: TcpKeepalive) -> Self {
+        self.config.tcp_keepalive = Some(tcp_keepalive);
+        self
+    }
+
+    pub fn echo_self(mut self, echo_self: bool) -> Self {
+        self.config.echo_self = echo_self;
+        self
+    }
+
+    pub fn loopback(mut self, loopback: bool) -> Self {
+        self.config.loopback = loopback;
+        self
+    }
+
+    pub fn left_reason(mut self, left_reason: bool) -> Self {
+        self.config.left_reason = left_reason;
+        self
+    }
+
+    pub fn number_per_sender(mut self, number_per_sender: bool) -> Self {
+        self.config.number_per_sender = number_per_sender;
+        self
+    }
+
+    pub fn history_len(mut self, history_len: usize) -> Self {
+        self.config.history_len = history_len;
+        self
+    }
+
+    pub fn history_ttl(mut self, history_ttl: Duration) -> Self {
+        self.config.history_ttl = history_ttl;
+        self
+    }
+
+    pub fn motd(mut self, motd: impl Into<String>) -> Self {
+        self.config.motd = Some(motd.into());
+        self
+    }
+
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.config.rate_limit = Some(rate_limit);
+        self
+    }
+
+    pub fn dedup(mut self, dedup: Dedup) -> Self {
+        self.config.dedup = Some(dedup);
+        self
+    }
+
+    pub fn filter(mut self, filter: impl Fn(&str) -> Filtered + Send + Sync + 'static) -> Self {
+        self.config.filter = Some(MessageFilter::new(filter));
+        self
+    }
+
+    pub fn outbound_queue_len(mut self, outbound_queue_len: usize) -> Self {
+        self.config.outbound_queue_len = outbound_queue_len;
+        self
+    }
+
+    pub fn client_ids(mut self, client_ids: ClientIds) -> Self {
+        self.config.client_ids = client_ids;
+        self
+    }
+
+    pub fn backpressure(mut self, backpressure: BackpressurePolicy) -> Self {
+        self.config.backpressure = backpressure;
+        self
+    }
+
+    pub fn max_queued_bytes(mut self, max_queued_bytes: usize) -> Self {
+        self.config.max_queued_bytes = Some(max_queued_bytes);
+        self
+    }
+
+    pub fn broadcast_yield_every(mut self, broadcast_yield_every: usize) -> Self {
+        self.config.broadcast_yield_every = broadcast_yield_every;
+        self
+    }
+
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.config.protocol = protocol;
+        self
+    }
+
+    pub fn prefixes(mut self, prefixes: Prefixes) -> Self {
+        self.config.prefixes = prefixes;
+        self
+    }
+
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.config.framing = framing;
+        self
+    }
+
+    pub fn delimiters(mut self, delimiters: impl IntoIterator<Item = u8>) -> Self {
+        self.config.delimiters = delimiters.into_iter().collect();
+        self
+    }
+
+    pub fn tls(mut self, tls: Tls) -> Self {
+        self.config.tls = Some(tls);
+        self
+    }
+
+    pub fn websocket(mut self, websocket: bool) -> Self {
+        self.config.websocket = websocket;
+        self
+    }
+
+    pub fn auth_token(mut self, auth_token: impl Into<String>) -> Self {
+        self.config.auth_token = Some(auth_token.into());
+        self
+    }
+
+    pub fn admin_token(mut self, admin_token: impl Into<String>) -> Self {
+        self.config.admin_token = Some(admin_token.into());
+        self
+    }
+
+    pub fn auth_timeout(mut self, auth_timeout: Duration) -> Self {
+        self.config.auth_timeout = auth_timeout;
+        self
+    }
+
+    pub fn health_probe_window(mut self, health_probe_window: Duration) -> Self {
+        self.config.health_probe_window = Some(health_probe_window);
+        self
+    }
+
+    pub fn max_handshakes(mut self, max_handshakes: usize) -> Self {
+        self.config.max_handshakes = Some(max_handshakes);
+        self
+    }
+
+    pub fn metrics_addr(mut self, metrics_addr: SocketAddr) -> Self {
+        self.config.metrics_addr = Some(metrics_addr);
+        self
+    }
+
+    pub fn log_throughput(mut self, log_throughput: bool) -> Self {
+        self.config.log_throughput = log_throughput;
+        self
+    }
+
+    pub fn log_file(mut self, log_file: impl Into<PathBuf>) -> Self {
+        self.config.log_file = Some(log_file.into());
+        self
+    }
+
+    pub fn drain_on_sigterm(mut self, drain_on_sigterm: bool) -> Self {
+        self.config.drain_on_sigterm = drain_on_sigterm;
+        self
+    }
+
+    pub fn drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.config.drain_timeout = drain_timeout;
+        self
+    }
+
+    pub fn shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.config.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    pub fn build(self) -> ServerConfig {
+        self.config
+    }
+}
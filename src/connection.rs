@@ -0,0 +1,624 @@
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+
+use futures::{
+    ready,
+    stream::{AbortHandle, Abortable, SelectAll},
+    SinkExt, Stream,
+};
+use tokio::{task::JoinHandle, time::Instant};
+use tracing::{debug, info, trace, warn};
+
+use crate::{
+    codec::{FrameError, OutFrame},
+    config::{BackpressurePolicy, Prefixes, ServerConfig},
+    dedup::RecentMessages,
+    framed::{Reader, Writer},
+    metrics::Metrics,
+    protocol::{Features, Outgoing, Protocol},
+    queue::{self, Budget, PushError, TryPushError},
+    rate_limit::TokenBucket,
+    server::ClientId,
+};
+
+/// how many connections the warning about running out of budget names
+const SLOWEST_REPORTED: usize = 5;
+
+/// the sending half of a connection
+///
+/// messages are queued and written out by a dedicated task so a client
+/// that is slow to read never holds up the event loop or anyone else
+///
+/// this is deliberately a queue per connection rather than one shared
+/// `tokio::sync::broadcast` channel: rooms, direct messages, replies and
+/// echo suppression all decide per recipient, and with a per connection
+/// queue those decisions stay in the event loop instead of being spread
+/// over every writer task
+pub(crate) struct Outbound {
+    // shared so a broadcast allocates the frame once no matter how many recipients
+    tx: queue::Sender,
+    /// resolves to whether everything queued made it out before the socket was shut down
+    writer: JoinHandle<bool>,
+    policy: BackpressurePolicy,
+    /// written by the writer task as frames actually make it onto the socket
+    bytes_out: Arc<AtomicU64>,
+    /// counts what the policy drops
+    metrics: Arc<Metrics>,
+}
+
+impl Outbound {
+    /// `reader` is aborted if writing fails or times out,
+    /// so the event loop finds out the client is gone even if it never hears from it again
+    pub(crate) fn spawn(
+        id: ClientId,
+        addr: Option<SocketAddr>,
+        sink: Writer,
+        budget: Arc<Budget>,
+        metrics: Arc<Metrics>,
+        config: &ServerConfig,
+        reader: AbortHandle,
+    ) -> Self {
+        let (tx, mut rx) = queue::bounded(config.outbound_queue_len, budget);
+        let policy = config.backpressure;
+        let write_timeout = config.write_timeout;
+        let slow_write_threshold = config.slow_write_threshold;
+        let bytes_out = Arc::new(AtomicU64::new(0));
+
+        let writer = tokio::spawn({
+            let bytes_out = Arc::clone(&bytes_out);
+
+            async move {
+                let mut sink = sink;
+
+                // once the sender is dropped whatever is left still gets written out
+                while let Some(msg) = rx.pop().await {
+                    let len = msg.len() as u64;
+                    let started = Instant::now();
+
+                    // sending flushes too, so every frame is on its way before the next is popped
+                    match tokio::time::timeout(write_timeout, sink.send(msg)).await {
+                        Ok(Ok(())) => {
+                            let took = started.elapsed();
+
+                            if slow_write_threshold.is_some_and(|threshold| took > threshold) {
+                                warn!(client = %id, ?addr, ?took, "writing to client {id} took {took:?}");
+                            }
+                        }
+                        Ok(Err(e)) => {
+                            debug!("writer stopped: {e}");
+                            reader.abort();
+                            return false;
+                        }
+                        Err(_) => {
+                            info!("write blocked for over {write_timeout:?}, giving up");
+                            reader.abort();
+                            return false;
+                        }
+                    }
+
+                    bytes_out.fetch_add(len, Ordering::Relaxed);
+                }
+
+                // shuts down the write side, so the peer reads everything up to a clean end
+                // rather than having the socket dropped under it
+                match tokio::time::timeout(write_timeout, sink.close()).await {
+                    Ok(Ok(())) => true,
+                    Ok(Err(e)) => {
+                        debug!("failed to close the connection cleanly: {e}");
+                        false
+                    }
+                    Err(_) => false,
+                }
+            }
+        });
+
+        Outbound {
+            tx,
+            writer,
+            policy,
+            bytes_out,
+            metrics,
+        }
+    }
+
+    /// queues `msg` without waiting on the socket unless the policy is to block
+    pub(crate) async fn send(&self, id: ClientId, msg: &OutFrame) -> Sent {
+        match self.tx.try_push(msg.clone()) {
+            Ok(()) => {
+                trace!("queued {msg:?} for {id}");
+                Sent::Queued
+            }
+            Err(TryPushError::Full(returned)) => match self.policy {
+                BackpressurePolicy::Block => {
+                    debug!("outbound queue for {id} is full, waiting for it to drain");
+                    match self.tx.push(returned).await {
+                        Ok(()) => Sent::Queued,
+                        Err(PushError::OverBudget) => self.drop_over_budget(id, msg),
+                        Err(PushError::Closed) => Sent::Failed,
+                    }
+                }
+                BackpressurePolicy::DropNewest => {
+                    debug!("outbound queue for {id} is full, dropping {msg:?}");
+                    self.metrics.dropped_total.inc("queue_full");
+                    Sent::Dropped
+                }
+                BackpressurePolicy::DropOldest => match self.tx.push_evicting(returned) {
+                    Ok(evicted) => {
+                        debug!("outbound queue for {id} is full, dropping {evicted:?}");
+                        self.metrics.dropped_total.inc("queue_full");
+                        // the new frame is what's queued now
+                        Sent::Queued
+                    }
+                    Err(queue::Closed) => Sent::Failed,
+                },
+                BackpressurePolicy::Disconnect => {
+                    info!("outbound queue for {id} is full, disconnecting");
+                    // the writer is stuck on the socket, don't wait for it to drain
+                    self.writer.abort();
+                    Sent::Failed
+                }
+            },
+            Err(TryPushError::OverBudget) => self.drop_over_budget(id, msg),
+            Err(TryPushError::Closed) => {
+                debug!("writer for {id} has stopped");
+                Sent::Failed
+            }
+        }
+    }
+
+    fn drop_over_budget(&self, id: ClientId, msg: &OutFrame) -> Sent {
+        debug!("too much is queued across all clients, dropping {msg:?} for {id}");
+        self.metrics.dropped_total.inc("over_budget");
+        Sent::Dropped
+    }
+
+    /// stops accepting messages, the writer exits once everything queued is written
+    fn close(self) -> JoinHandle<bool> {
+        self.writer
+    }
+}
+
+/// what became of a frame handed to [`Outbound::send`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Sent {
+    Queued,
+    /// discarded by the backpressure policy or for being over budget, the connection carries on
+    Dropped,
+    /// the connection should be dropped
+    Failed,
+}
+
+/// how closing every connection went
+#[derive(Debug, Default)]
+pub(crate) struct Closed {
+    /// got everything queued for them, the goodbye included, then a clean end of stream
+    pub(crate) flushed: usize,
+    /// writing to them failed along the way
+    pub(crate) failed: usize,
+    /// still being written to when time ran out
+    pub(crate) cut_off: usize,
+}
+
+/// the receiving half of a connection
+pub(crate) struct FramedStream {
+    inner: Abortable<Reader>,
+    id: ClientId,
+    /// `None` for unix socket peers
+    ip: Option<IpAddr>,
+    closed: bool,
+    /// set once the connection was removed, from then on the id may already
+    /// belong to a new client that mustn't hear of this one ending
+    removed: Rc<Cell<bool>>,
+}
+
+impl Stream for FramedStream {
+    // `None` in the second slot means the underlying stream has ended,
+    // SelectAll would otherwise drop us silently and we'd never know who left
+    type Item = (ClientId, Option<IpAddr>, Option<Result<Bytes, FrameError>>);
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        if self.closed || self.removed.get() {
+            return std::task::Poll::Ready(None);
+        }
+
+        let (id, ip) = (self.id, self.ip);
+        let res = ready!(Pin::new(&mut self.inner).poll_next(cx));
+
+        if res.is_none() {
+            self.closed = true;
+        }
+
+        std::task::Poll::Ready(Some((id, ip, res)))
+    }
+}
+
+/// everything the event loop tracks about a single client's socket
+pub(crate) struct Connection {
+    /// `None` for unix socket peers
+    pub(crate) addr: Option<SocketAddr>,
+    outbound: Outbound,
+    pub(crate) last_activity: Instant,
+    pub(crate) missed_pongs: u32,
+    pub(crate) bucket: TokenBucket,
+    /// only filled in when deduplicating
+    pub(crate) recent: RecentMessages,
+    /// bytes received, not counting framing
+    pub(crate) bytes_in: u64,
+    /// may use admin commands
+    pub(crate) admin: bool,
+    /// everything sent to the client is deflated
+    pub(crate) compress: bool,
+    /// longest line the client may send, at most the configured ceiling
+    pub(crate) max_line_length: usize,
+    /// what the client settled on in `HELLO`, everything on offer otherwise
+    pub(crate) features: Features,
+    /// sent `MODE observer`, only receives from then on and is left out of `/list`
+    pub(crate) observer: bool,
+    /// how many messages from the client were relayed, what the next one is numbered
+    pub(crate) relayed: u64,
+    reader: AbortHandle,
+    removed: Rc<Cell<bool>>,
+}
+
+impl Connection {
+    /// deflates `msg` first if the client asked for compression,
+    /// returns false if the connection should be dropped
+    pub(crate) async fn send(&self, id: ClientId, msg: &Bytes) -> bool {
+        let frame = if self.compress {
+            OutFrame::deflate(msg)
+        } else {
+            OutFrame::Plain(msg.clone())
+        };
+
+        self.outbound.send(id, &frame).await != Sent::Failed
+    }
+
+    /// the writer gave up, either on its own or because the queue overflowed
+    pub(crate) fn writer_stopped(&self) -> bool {
+        self.outbound.writer.is_finished()
+    }
+
+    /// bytes written so far, not counting framing
+    pub(crate) fn bytes_out(&self) -> u64 {
+        self.outbound.bytes_out.load(Ordering::Relaxed)
+    }
+
+    /// bytes waiting to be written
+    pub(crate) fn queued_bytes(&self) -> usize {
+        self.outbound.tx.queued_bytes()
+    }
+
+    /// frames waiting to be written
+    pub(crate) fn queued_frames(&self) -> usize {
+        self.outbound.tx.queued_frames()
+    }
+}
+
+/// every connected client
+///
+/// connections are keyed by id so direct sends and lookups don't scan,
+/// while the receiving halves are polled fairly through a `SelectAll`
+pub(crate) struct Connections {
+    by_id: HashMap<ClientId, Connection>,
+    pub(crate) readers: SelectAll<FramedStream>,
+    protocol: Protocol,
+    prefixes: Prefixes,
+    /// how many connections each host has open
+    per_ip: HashMap<IpAddr, usize>,
+    /// when each host last had a connection accepted, only kept with a cooldown
+    connected_at: HashMap<IpAddr, Instant>,
+    reconnect_cooldown: Option<Duration>,
+    /// when `connected_at` was last rid of hosts whose cooldown is over
+    swept_at: Instant,
+    /// recipients a broadcast gets through before yielding, never yields when 0
+    yield_every: usize,
+    /// say why in `LEFT`
+    pub(crate) left_reason: bool,
+    /// what new connections may send until they ask for something else
+    max_line_length: usize,
+    /// shared by every connection's outbound queue
+    budget: Arc<Budget>,
+    /// whether the budget was found exhausted last time, so it's only warned about once
+    over_budget: Cell<bool>,
+    pub(crate) metrics: Arc<Metrics>,
+}
+
+impl Connections {
+    pub(crate) fn new(config: &ServerConfig, metrics: Arc<Metrics>) -> Self {
+        Connections {
+            by_id: HashMap::new(),
+            readers: SelectAll::new(),
+            protocol: config.protocol,
+            prefixes: config.prefixes.clone(),
+            per_ip: HashMap::new(),
+            connected_at: HashMap::new(),
+            reconnect_cooldown: config.reconnect_cooldown,
+            swept_at: Instant::now(),
+            yield_every: config.broadcast_yield_every,
+            left_reason: config.left_reason,
+            max_line_length: config
+                .default_max_line_length
+                .map_or(config.max_line_length, |default| {
+                    default.min(config.max_line_length)
+                }),
+            budget: Arc::new(Budget::new(config.max_queued_bytes)),
+            over_budget: Cell::new(false),
+            metrics,
+        }
+    }
+
+    /// for the outbound queues of new connections
+    pub(crate) fn budget(&self) -> Arc<Budget> {
+        Arc::clone(&self.budget)
+    }
+
+    /// bytes waiting to be written across every connection
+    pub(crate) fn queued_bytes(&self) -> usize {
+        self.budget.queued()
+    }
+
+    /// warns once about whoever is holding up the most bytes when the budget runs out,
+    /// then again only after it has recovered
+    fn check_budget(&self) {
+        let exhausted = self.budget.take_exhausted();
+
+        if exhausted && !self.over_budget.get() {
+            let mut slowest: Vec<_> = self
+                .iter()
+                .map(|(id, conn)| (conn.queued_bytes(), id))
+                .collect();
+            slowest.sort_unstable_by(|a, b| b.cmp(a));
+            slowest.truncate(SLOWEST_REPORTED);
+
+            let slowest = slowest
+                .iter()
+                .map(|(queued, id)| format!("{id} ({queued} bytes)"))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            warn!(
+                "{} bytes queued across all clients, dropping messages until the slowest catch up: {slowest}",
+                self.budget.queued()
+            );
+        }
+
+        self.over_budget.set(exhausted);
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        id: ClientId,
+        addr: Option<SocketAddr>,
+        admin: bool,
+        (inner, handle): (Abortable<Reader>, AbortHandle),
+        outbound: Outbound,
+    ) {
+        let removed = Rc::new(Cell::new(false));
+        self.readers.push(FramedStream {
+            inner,
+            id,
+            ip: addr.map(|addr| addr.ip()),
+            closed: false,
+            removed: Rc::clone(&removed),
+        });
+
+        if let Some(addr) = addr {
+            *self.per_ip.entry(addr.ip()).or_default() += 1;
+            self.record_connect(addr.ip());
+        }
+
+        let connection = Connection {
+            addr,
+            outbound,
+            last_activity: Instant::now(),
+            missed_pongs: 0,
+            bucket: TokenBucket::new(),
+            recent: RecentMessages::default(),
+            bytes_in: 0,
+            admin,
+            compress: false,
+            max_line_length: self.max_line_length,
+            features: Features::offered(self.protocol),
+            observer: false,
+            relayed: 0,
+            reader: handle,
+            removed,
+        };
+
+        self.by_id.insert(id, connection);
+    }
+
+    /// returns the connection if one with the given id was actually removed
+    pub(crate) fn remove(&mut self, id: ClientId) -> Option<Connection> {
+        let connection = self.by_id.remove(&id)?;
+
+        // SelectAll has no keyed removal, aborting ends the reader
+        // so it gets dropped the next time it's polled, without a word
+        connection.removed.set(true);
+        connection.reader.abort();
+
+        if let Some(ip) = connection.addr.map(|addr| addr.ip()) {
+            if let Some(count) = self.per_ip.get_mut(&ip) {
+                *count -= 1;
+
+                if *count == 0 {
+                    self.per_ip.remove(&ip);
+                }
+            }
+        }
+
+        Some(connection)
+    }
+
+    pub(crate) fn get(&self, id: ClientId) -> Option<&Connection> {
+        self.by_id.get(&id)
+    }
+
+    pub(crate) fn get_mut(&mut self, id: ClientId) -> Option<&mut Connection> {
+        self.by_id.get_mut(&id)
+    }
+
+    pub(crate) fn contains(&self, id: ClientId) -> bool {
+        self.by_id.contains_key(&id)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.by_id.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.by_id.is_empty()
+    }
+
+    /// how many connections are open from the given host
+    pub(crate) fn count_from(&self, ip: IpAddr) -> usize {
+        self.per_ip.get(&ip).copied().unwrap_or_default()
+    }
+
+    /// whether the given host had a connection accepted less than the cooldown ago
+    pub(crate) fn connected_recently(&self, ip: IpAddr) -> bool {
+        self.reconnect_cooldown
+            .zip(self.connected_at.get(&ip))
+            .is_some_and(|(cooldown, connected_at)| connected_at.elapsed() < cooldown)
+    }
+
+    fn record_connect(&mut self, ip: IpAddr) {
+        let Some(cooldown) = self.reconnect_cooldown else {
+            return;
+        };
+
+        let now = Instant::now();
+
+        // at most once per cooldown, every entry swept is at least that old anyway
+        if now.duration_since(self.swept_at) >= cooldown {
+            self.connected_at
+                .retain(|_, connected_at| now.duration_since(*connected_at) < cooldown);
+            self.swept_at = now;
+        }
+
+        self.connected_at.insert(ip, now);
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (ClientId, &Connection)> {
+        self.by_id.iter().map(|(id, conn)| (*id, conn))
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = (ClientId, &mut Connection)> {
+        self.by_id.iter_mut().map(|(id, conn)| (*id, conn))
+    }
+
+    /// sends `msg` to every connection whose id passes `filter`
+    ///
+    /// a failed send doesn't stop delivery to everyone else,
+    /// the ids of the connections that failed are returned instead
+    pub(crate) async fn broadcast(
+        &self,
+        msg: &Outgoing<'_>,
+        filter: impl Fn(ClientId) -> bool,
+    ) -> Vec<ClientId> {
+        self.broadcast_with(msg, filter, |_| {}).await
+    }
+
+    /// like [`Connections::broadcast`], calling `queued` with every recipient that didn't have `msg` dropped
+    pub(crate) async fn broadcast_with(
+        &self,
+        msg: &Outgoing<'_>,
+        filter: impl Fn(ClientId) -> bool,
+        mut queued: impl FnMut(ClientId),
+    ) -> Vec<ClientId> {
+        // encoded once and shared between every recipient
+        let msg = self.protocol.encode(msg, &self.prefixes);
+        let plain = OutFrame::Plain(msg.clone());
+        // likewise deflated once, the first time a recipient wants it that way
+        let mut deflated = None;
+        let mut failed = Vec::new();
+
+        for (sent, (id, conn)) in self.iter().filter(|(id, _)| filter(*id)).enumerate() {
+            let frame = if conn.compress {
+                deflated.get_or_insert_with(|| OutFrame::deflate(&msg))
+            } else {
+                &plain
+            };
+
+            match conn.outbound.send(id, frame).await {
+                Sent::Queued => queued(id),
+                Sent::Dropped => {}
+                Sent::Failed => failed.push(id),
+            }
+
+            // on a single thread a big fan out would otherwise starve the writers and ctrl-c
+            if self.yield_every != 0 && (sent + 1) % self.yield_every == 0 {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        self.check_budget();
+        failed
+    }
+
+    /// sends `msg` to just the connection with the given id,
+    /// returning the id back if the send failed
+    pub(crate) async fn send_to(&self, id: ClientId, msg: &Outgoing<'_>) -> Option<ClientId> {
+        let connection = self.get(id)?;
+        let msg = self.protocol.encode(msg, &self.prefixes);
+        let sent = connection.send(id, &msg).await;
+
+        self.check_budget();
+        (!sent).then_some(id)
+    }
+
+    /// closes every connection, giving them until `deadline` to write out what's queued
+    /// and shut the socket down, then cuts off whoever is left
+    pub(crate) async fn close(self, deadline: Instant) -> Closed {
+        let writers = self.by_id.into_iter().map(|(id, connection)| {
+            let addr = connection.addr;
+            let mut writer = connection.outbound.close();
+
+            async move {
+                let flushed = tokio::time::timeout_at(deadline, &mut writer).await;
+
+                match flushed {
+                    Ok(Ok(true)) => {
+                        debug!(client = %id, ?addr, "flushed everything to client {id}")
+                    }
+                    Ok(_) => {
+                        debug!(client = %id, ?addr, "client {id} went away before it was flushed")
+                    }
+                    Err(_) => {
+                        debug!(client = %id, ?addr, "cut off client {id} before it was flushed");
+                        writer.abort();
+                    }
+                }
+
+                flushed.map(|res| res.unwrap_or(false))
+            }
+        });
+
+        let mut closed = Closed::default();
+
+        for flushed in futures::future::join_all(writers).await {
+            match flushed {
+                Ok(true) => closed.flushed += 1,
+                Ok(false) => closed.failed += 1,
+                Err(_) => closed.cut_off += 1,
+            }
+        }
+
+        closed
+    }
+}
@@ -0,0 +1,45 @@
+use std::{
+    collections::VecDeque,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use tokio::time::Instant;
+
+use crate::config::Dedup;
+
+/// hashes of the messages a client sent last, oldest first
+#[derive(Debug, Default)]
+pub(crate) struct RecentMessages {
+    seen: VecDeque<(u64, Instant)>,
+}
+
+impl RecentMessages {
+    /// returns true if `msg` repeats one relayed within the window, otherwise remembers it
+    ///
+    /// repeats aren't remembered, so the window runs from the copy that went out
+    pub(crate) fn is_duplicate(&mut self, msg: &[u8], dedup: Dedup) -> bool {
+        let now = Instant::now();
+        while self
+            .seen
+            .front()
+            .is_some_and(|(_, at)| now.duration_since(*at) >= dedup.window)
+        {
+            self.seen.pop_front();
+        }
+
+        let mut hasher = DefaultHasher::new();
+        msg.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if self.seen.iter().any(|(seen, _)| *seen == hash) {
+            return true;
+        }
+
+        self.seen.push_back((hash, now));
+        while self.seen.len() > dedup.cache_size {
+            self.seen.pop_front();
+        }
+
+        false
+    }
+}
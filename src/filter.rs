@@ -0,0 +1,35 @@
+use std::{fmt, sync::Arc};
+
+/// what a [`MessageFilter`] decided to do with a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filtered {
+    /// broadcast the message unchanged
+    Pass,
+    /// broadcast this instead
+    Rewrite(String),
+    /// don't broadcast anything, the sender is told `ERR:blocked`
+    Reject,
+}
+
+/// decides what happens to every chat message before it's broadcast
+///
+/// frames that aren't valid utf-8 are shown to the filter lossily converted,
+/// they're only ever passed through as the original bytes
+#[derive(Clone)]
+pub struct MessageFilter(Arc<dyn Fn(&str) -> Filtered + Send + Sync>);
+
+impl MessageFilter {
+    pub fn new(filter: impl Fn(&str) -> Filtered + Send + Sync + 'static) -> Self {
+        MessageFilter(Arc::new(filter))
+    }
+
+    pub(crate) fn apply(&self, msg: &str) -> Filtered {
+        (self.0)(msg)
+    }
+}
+
+impl fmt::Debug for MessageFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MessageFilter(..)")
+    }
+}
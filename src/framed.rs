@@ -0,0 +1,131 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::{
+    stream::{SplitSink, SplitStream},
+    Sink, Stream, StreamExt,
+};
+use tokio::io::{ReadHalf, WriteHalf};
+use tokio_tungstenite::{
+    tungstenite::{protocol::WebSocketConfig, Message, Utf8Bytes},
+    WebSocketStream,
+};
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+use crate::{
+    codec::{FrameCodec, FrameError, OutFrame},
+    transport::Socket,
+};
+
+/// the receiving half of a socket, yielding one frame per message
+///
+/// websocket messages already arrive framed so they skip the codec entirely
+#[derive(Debug)]
+pub(crate) enum Reader {
+    Codec(FramedRead<ReadHalf<Socket>, FrameCodec>),
+    WebSocket(SplitStream<WebSocketStream<Socket>>),
+}
+
+/// the sending half of a socket
+#[derive(Debug)]
+pub(crate) enum Writer {
+    Codec(FramedWrite<WriteHalf<Socket>, FrameCodec>),
+    WebSocket(SplitSink<WebSocketStream<Socket>, Message>),
+}
+
+/// splits a socket into frames delimited by `codec`
+pub(crate) fn with_codec(sock: Socket, codec: &FrameCodec) -> (Reader, Writer) {
+    let (read, write) = tokio::io::split(sock);
+
+    (
+        Reader::Codec(FramedRead::new(read, codec.clone())),
+        Writer::Codec(FramedWrite::new(write, codec.clone())),
+    )
+}
+
+/// completes the http upgrade, messages longer than `max_length` bytes are refused
+pub(crate) async fn with_websocket(
+    sock: Socket,
+    max_length: usize,
+) -> Result<(Reader, Writer), FrameError> {
+    let config = WebSocketConfig::default().max_message_size(Some(max_length));
+    let (write, read) = tokio_tungstenite::accept_async_with_config(sock, Some(config))
+        .await?
+        .split();
+
+    Ok((Reader::WebSocket(read), Writer::WebSocket(write)))
+}
+
+impl Stream for Reader {
+    type Item = Result<Bytes, FrameError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.get_mut() {
+            Reader::Codec(inner) => Pin::new(inner)
+                .poll_next(cx)
+                .map(|frame| frame.map(|res| res.and_then(Into::into))),
+            Reader::WebSocket(inner) => loop {
+                let msg = match futures::ready!(Pin::new(&mut *inner).poll_next(cx)) {
+                    Some(Ok(msg)) => msg,
+                    Some(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+                    None => return Poll::Ready(None),
+                };
+
+                match msg {
+                    Message::Text(text) => return Poll::Ready(Some(Ok(text.into()))),
+                    Message::Binary(bytes) => return Poll::Ready(Some(Ok(bytes))),
+                    Message::Close(_) => return Poll::Ready(None),
+                    // pings are answered by tungstenite itself
+                    Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => continue,
+                }
+            },
+        }
+    }
+}
+
+impl Sink<OutFrame> for Writer {
+    type Error = FrameError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self.get_mut() {
+            Writer::Codec(inner) => Pin::new(inner).poll_ready(cx),
+            Writer::WebSocket(inner) => Pin::new(inner).poll_ready(cx).map_err(Into::into),
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: OutFrame) -> Result<(), Self::Error> {
+        match self.get_mut() {
+            Writer::Codec(inner) => Pin::new(inner).start_send(item),
+            Writer::WebSocket(inner) => {
+                // browsers hand text messages over as strings, so send text whenever possible,
+                // websocket messages are framed already so deflated ones need no length
+                let msg = match item {
+                    OutFrame::Plain(item) => match Utf8Bytes::try_from(item.clone()) {
+                        Ok(text) => Message::Text(text),
+                        Err(_) => Message::Binary(item),
+                    },
+                    OutFrame::Deflated(item) => Message::Binary(item),
+                };
+
+                Pin::new(inner).start_send(msg).map_err(Into::into)
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self.get_mut() {
+            Writer::Codec(inner) => Pin::new(inner).poll_flush(cx),
+            Writer::WebSocket(inner) => Pin::new(inner).poll_flush(cx).map_err(Into::into),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self.get_mut() {
+            Writer::Codec(inner) => Pin::new(inner).poll_close(cx),
+            Writer::WebSocket(inner) => Pin::new(inner).poll_close(cx).map_err(Into::into),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use tokio::time::Instant;
+
+/// a chat message kept around for clients that join later
+#[derive(Debug)]
+pub(crate) struct Recorded {
+    pub(crate) seq: u64,
+    pub(crate) from: String,
+    /// milliseconds since the unix epoch
+    pub(crate) sent_at: u128,
+    pub(crate) content: Bytes,
+}
+
+/// what's been said in a single room
+#[derive(Debug, Default)]
+struct Room {
+    /// the sequence number of the next message
+    next_seq: u64,
+    /// the most recent messages, oldest first
+    entries: VecDeque<Recorded>,
+    /// when the last client left, `None` while anyone is in it
+    emptied_at: Option<Instant>,
+}
+
+/// the numbering and recent messages of every room something was said in
+///
+/// both outlive a room emptying by `ttl`, so a quick rejoin carries on where it left off
+#[derive(Debug)]
+pub(crate) struct History {
+    rooms: HashMap<String, Room>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl History {
+    /// keeps no messages at all when `capacity` is 0, only the numbering
+    pub(crate) fn new(capacity: usize, ttl: Duration) -> Self {
+        History {
+            rooms: HashMap::new(),
+            capacity,
+            ttl,
+        }
+    }
+
+    /// numbers the next message sent in `room`
+    pub(crate) fn next_seq(&mut self, room: &str) -> u64 {
+        // only allocate the room's name the first time something is said in it
+        if let Some(state) = self.rooms.get_mut(room) {
+            state.next_seq += 1;
+            return state.next_seq - 1;
+        }
+
+        self.rooms.insert(
+            room.to_owned(),
+            Room {
+                next_seq: 1,
+                ..Room::default()
+            },
+        );
+        0
+    }
+
+    /// the number the next message sent in `room` will get, without taking it
+    pub(crate) fn peek_seq(&self, room: &str) -> u64 {
+        self.rooms.get(room).map_or(0, |state| state.next_seq)
+    }
+
+    /// records a message sent in `room`, forgetting the room's oldest one once full
+    pub(crate) fn push(&mut self, room: &str, recorded: Recorded) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let Some(state) = self.rooms.get_mut(room) else {
+            return;
+        };
+
+        if state.entries.len() == self.capacity {
+            state.entries.pop_front();
+        }
+
+        state.entries.push_back(recorded);
+    }
+
+    /// the recent messages in `room`, oldest first
+    pub(crate) fn iter(&self, room: &str) -> impl Iterator<Item = &Recorded> {
+        self.rooms
+            .get(room)
+            .into_iter()
+            .flat_map(|state| state.entries.iter())
+    }
+
+    /// starts the clock on forgetting `room`, along with any other room whose time is up
+    pub(crate) fn emptied(&mut self, room: &str) {
+        let now = Instant::now();
+
+        if let Some(state) = self.rooms.get_mut(room) {
+            state.emptied_at = Some(now);
+        }
+
+        self.rooms.retain(|_, state| {
+            state
+                .emptied_at
+                .is_none_or(|emptied_at| now.duration_since(emptied_at) < self.ttl)
+        });
+    }
+
+    /// someone is in `room` again, so it's kept for as long as they are
+    pub(crate) fn occupied(&mut self, room: &str) {
+        let Some(state) = self.rooms.get_mut(room) else {
+            return;
+        };
+
+        match state.emptied_at.take() {
+            // nobody may have emptied another room since, so this one may be overdue
+            Some(emptied_at) if emptied_at.elapsed() >= self.ttl => {
+                self.rooms.remove(room);
+            }
+            _ => {}
+        }
+    }
+}
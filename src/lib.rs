@@ -0,0 +1,23 @@
+//! a tcp broadcast server that runs on a single thread
+
+mod audit;
+mod client_id;
+mod codec;
+mod command;
+pub mod config;
+mod connection;
+mod dedup;
+mod filter;
+mod framed;
+mod history;
+mod message;
+mod metrics;
+mod protocol;
+mod queue;
+mod rate_limit;
+pub mod server;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+mod tls;
+mod transport;
+mod udp;
@@ -1,23 +1,486 @@
-mod server;
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::{
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    num::NonZeroUsize,
+    path::PathBuf,
+    process::ExitCode,
+    time::Duration,
+};
 
-use server::serve;
+use broadcast_server_example::{
+    config::{
+        BackpressurePolicy, ClientIds, ConfigFileError, Dedup, Framing, Keepalive, Prefixes,
+        Protocol, RateLimit, ServerConfig, TcpKeepalive, Tls, DEFAULT_BROADCAST_YIELD_EVERY,
+        DEFAULT_DELIMITERS, DEFAULT_MAX_LINE_LENGTH, DEFAULT_OUTBOUND_QUEUE_LEN,
+    },
+    server::serve,
+};
 
-use clap::Parser;
+use clap::{
+    error::ErrorKind, parser::ValueSource, ArgAction, ArgMatches, CommandFactory, FromArgMatches,
+    Parser, ValueEnum,
+};
+use ipnet::IpNet;
+use tokio::sync::mpsc;
+
+/// how log lines are written to stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    /// human readable lines
+    #[default]
+    Text,
+    /// one json object per line, for log aggregation
+    Json,
+}
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// socket address to serve requests from
-    #[arg(default_value_t = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 8888)))]
-    bind: SocketAddr,
+    /// toml file to read the configuration from, see `examples/server.toml`,
+    /// flags given as well take precedence over it,
+    /// except for `allow` and `deny` which are read again from the file on SIGHUP
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// how log lines are formatted
+    #[arg(long, env = "LOG_FORMAT", value_enum, default_value_t = LogFormat::default())]
+    log_format: LogFormat,
+
+    /// socket address to serve requests from, may be given more than once
+    #[arg(long, default_values_t = [SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 8888))])]
+    bind: Vec<SocketAddr>,
+
+    /// let ipv4 clients connect to an ipv6 `--bind` address as well, e.g. `--bind [::]:8888`
+    #[arg(long)]
+    dual_stack: bool,
+
+    /// expect a PROXY protocol v1 header from a load balancer in front of every tcp connection
+    #[arg(long)]
+    proxy_protocol: bool,
+
+    /// listen on this unix socket path instead of `--bind`
+    #[arg(long)]
+    unix_socket: Option<PathBuf>,
+
+    /// also take datagrams on this address, each broadcast as a message from its sender, best effort
+    #[arg(long)]
+    udp_bind: Option<SocketAddr>,
+
+    /// seconds a udp sender keeps being sent everyone else's datagrams, receive only when unset
+    #[arg(long)]
+    udp_peer_ttl: Option<u64>,
+
+    /// longest line or frame in bytes any client may send, even after asking for more
+    #[arg(long, default_value_t = DEFAULT_MAX_LINE_LENGTH)]
+    max_line_length: usize,
+
+    /// longest line or frame in bytes a client may send until it sends `MAXLEN <bytes>`,
+    /// `--max-line-length` when unset
+    #[arg(long)]
+    default_max_line_length: Option<usize>,
+
+    /// longest chat message in bytes that's broadcast, only `--max-line-length` applies when unset
+    #[arg(long)]
+    max_message_bytes: Option<usize>,
+
+    /// most clients connected at once, unlimited when unset
+    #[arg(long)]
+    max_connections: Option<usize>,
+
+    /// most clients connected at once from a single ip, unlimited when unset
+    #[arg(long)]
+    max_connections_per_ip: Option<usize>,
+
+    /// seconds an ip has to wait after connecting before it may connect again, no wait when unset
+    #[arg(long)]
+    reconnect_cooldown: Option<u64>,
+
+    /// network allowed to connect, e.g. `10.0.0.0/8`, may be repeated, everyone when unset
+    #[arg(long)]
+    allow: Vec<IpNet>,
+
+    /// network never allowed to connect, takes precedence over `--allow`, may be repeated
+    #[arg(long)]
+    deny: Vec<IpNet>,
+
+    /// seconds a client may stay silent before being disconnected
+    #[arg(long, default_value_t = 300)]
+    idle_timeout: u64,
+
+    /// seconds writing to a client may take before it's disconnected
+    #[arg(long, default_value_t = 30)]
+    write_timeout: u64,
+
+    /// seconds after which a write to a client is warned about as slow, e.g. `0.1`, disabled when unset
+    #[arg(long, value_parser = secs)]
+    slow_write_threshold: Option<Duration>,
+
+    /// seconds between keepalive pings, disabled when unset
+    #[arg(long)]
+    keepalive_interval: Option<u64>,
+
+    /// consecutive unanswered pings before a client is disconnected
+    #[arg(long, default_value_t = 3)]
+    keepalive_max_missed: u32,
+
+    /// send small lines right away, `--tcp-nodelay false` lets nagle's algorithm batch them
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    tcp_nodelay: bool,
+
+    /// seconds a connection may sit idle before the kernel starts probing it, system default when unset
+    #[arg(long)]
+    tcp_keepalive_time: Option<u64>,
+
+    /// seconds between unanswered kernel keepalive probes
+    #[arg(long, default_value_t = 10)]
+    tcp_keepalive_interval: u64,
+
+    /// unanswered kernel keepalive probes before a connection is given up on
+    #[arg(long, default_value_t = 3)]
+    tcp_keepalive_retries: u32,
+
+    /// also send clients their own messages back
+    #[arg(long)]
+    echo_self: bool,
+
+    /// send clients only their own messages back, for benchmarking with a single client
+    #[arg(long)]
+    loopback: bool,
+
+    /// say why a client left, e.g. `LEFT:3 idle`
+    #[arg(long)]
+    left_reason: bool,
+
+    /// number each client's messages, e.g. `MESSAGE:7:3#2 ...` for the third from client 3
+    #[arg(long)]
+    number_per_sender: bool,
+
+    /// how many recent messages clients are sent when they join a room
+    #[arg(long, default_value_t = 0)]
+    history_len: usize,
+
+    /// seconds a room's history is kept once everyone has left it
+    #[arg(long, default_value_t = 300)]
+    history_ttl: u64,
+
+    /// text file whose lines every new client is sent after `LOGIN`, e.g. the rules
+    #[arg(long)]
+    motd_file: Option<PathBuf>,
+
+    /// messages per second each client may send, unlimited when unset
+    #[arg(long)]
+    rate_limit: Option<f64>,
+
+    /// messages a client may send in a burst before the rate limit kicks in
+    #[arg(long, default_value_t = 10)]
+    rate_limit_burst: u32,
+
+    /// seconds within which a client repeating one of its recent messages is ignored, never when unset
+    #[arg(long, value_parser = secs)]
+    dedup_window: Option<Duration>,
+
+    /// how many of each client's recent messages are checked for repeats
+    #[arg(long, default_value_t = 8)]
+    dedup_cache_size: usize,
+
+    /// how many messages may wait to be written to a single client
+    #[arg(long, default_value_t = DEFAULT_OUTBOUND_QUEUE_LEN)]
+    outbound_queue_len: usize,
+
+    /// what to do once a client's outbound queue fills up
+    #[arg(long, value_enum, default_value_t = BackpressurePolicy::default())]
+    backpressure: BackpressurePolicy,
+
+    /// how clients are numbered, ids other than monotonic ones may clash and turn clients away
+    #[arg(long, value_enum, default_value_t = ClientIds::default())]
+    client_ids: ClientIds,
+
+    /// most bytes waiting to be written across every client together, unlimited when unset
+    #[arg(long)]
+    max_queued_bytes: Option<usize>,
+
+    /// how many clients a broadcast reaches before letting the rest of the server run, 0 never yields
+    #[arg(long, default_value_t = DEFAULT_BROADCAST_YIELD_EVERY)]
+    broadcast_yield_every: usize,
+
+    /// how lines are encoded on the wire
+    #[arg(long, value_enum, default_value_t = Protocol::default())]
+    protocol: Protocol,
+
+    /// what starts the line telling a client its id, separator included
+    #[arg(long, default_value_t = Prefixes::default().login)]
+    login_prefix: String,
+
+    /// what starts a broadcast message, separator included
+    #[arg(long, default_value_t = Prefixes::default().message)]
+    message_prefix: String,
+
+    /// how frames are delimited on the wire
+    #[arg(long, value_enum, default_value_t = Framing::default())]
+    framing: Framing,
+
+    /// byte values that end a frame with `--framing delimited`, e.g. `--delimiter 0` for NUL,
+    /// outgoing frames are ended with the first one
+    #[arg(long = "delimiter", default_values_t = DEFAULT_DELIMITERS.to_vec())]
+    delimiters: Vec<u8>,
+
+    /// pem encoded certificate chain, enables tls together with `--tls-key`
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// pem encoded private key, enables tls together with `--tls-cert`
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// speak websocket instead of raw frames, `--framing` is ignored
+    #[arg(long)]
+    websocket: bool,
+
+    /// shared secret clients must send as `AUTH <token>` before they join
+    #[arg(long)]
+    auth_token: Option<String>,
+
+    /// clients that authenticate with this token instead may kick others, needs `--auth-token`
+    #[arg(long)]
+    admin_token: Option<String>,
+
+    /// seconds a client gets to authenticate before being disconnected
+    #[arg(long, default_value_t = 10)]
+    auth_timeout: u64,
+
+    /// seconds a new tcp connection may take to send `HEALTH`, answered with `OK` and closed,
+    /// clients that don't speak first are greeted that much later, e.g. `0.05`, disabled when unset
+    #[arg(long, value_parser = secs)]
+    health_probe_window: Option<Duration>,
+
+    /// most clients handshaking or authenticating at once, unlimited when unset
+    #[arg(long)]
+    max_handshakes: Option<usize>,
+
+    /// socket address to serve prometheus metrics from at `/metrics`, disabled when unset
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// log messages per second and the number of connections every second
+    #[arg(long)]
+    log_throughput: bool,
+
+    /// append every broadcast message to this file
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+
+    /// seconds clients may stay connected after SIGTERM before shutting down anyway
+    #[arg(long, default_value_t = 30)]
+    drain_timeout: u64,
+
+    /// seconds clients get to receive the goodbye once shutting down before they're cut off
+    #[arg(long, default_value_t = 5)]
+    shutdown_timeout: u64,
+
+    /// write to sockets from this many worker threads, everything runs on one thread when unset
+    #[arg(long)]
+    worker_threads: Option<NonZeroUsize>,
+}
+
+fn main() -> ExitCode {
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    match args.log_format {
+        LogFormat::Text => tracing_subscriber::fmt().init(),
+        LogFormat::Json => tracing_subscriber::fmt().json().init(),
+    }
+
+    // the event loop itself always stays on this thread, with workers around
+    // the writer tasks get spread over them instead of taking turns with it
+    let runtime = match args.worker_threads {
+        Some(threads) => tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(threads.get())
+            .enable_all()
+            .build(),
+        None => tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build(),
+    };
+    let runtime = match runtime {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("error: failed to start the runtime: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut config = server_config(args, &matches)
+        .unwrap_or_else(|e| Args::command().error(ErrorKind::Io, e).exit());
+    // the process is the server's alone, so its signals are too
+    config.drain_on_sigterm = true;
+    config.reload_on_sighup = true;
+
+    // nothing to announce from the command line
+    let (_, announcements) = mpsc::channel(1);
+
+    // printed rather than debug formatted, the message says which address was the problem
+    match runtime.block_on(serve(config, announcements, std::future::pending())) {
+        Ok(stats) => {
+            println!("{stats}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), std::io::Error> {
-    tracing_subscriber::fmt::init();
+/// the config file if there is one, with whatever flags were given on top
+fn server_config(args: Args, matches: &ArgMatches) -> Result<ServerConfig, ConfigFileError> {
+    let (mut config, from_file) = match &args.config {
+        Some(path) => (ServerConfig::from_file(path)?, true),
+        None => (ServerConfig::default(), false),
+    };
+
+    // flag defaults only count without a file, they'd otherwise undo everything in it
+    let given = |id: &str| {
+        !from_file
+            || matches!(
+                matches.value_source(id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+    };
+
+    macro_rules! merge {
+        ($($field:ident => $value:expr),* $(,)?) => {
+            $(
+                if given(stringify!($field)) {
+                    config.$field = $value;
+                }
+            )*
+        };
+    }
 
-    let args = Args::parse();
+    merge! {
+        bind => args.bind,
+        dual_stack => args.dual_stack,
+        proxy_protocol => args.proxy_protocol,
+        unix_socket => args.unix_socket,
+        udp_bind => args.udp_bind,
+        udp_peer_ttl => args.udp_peer_ttl.map(Duration::from_secs),
+        max_line_length => args.max_line_length,
+        default_max_line_length => args.default_max_line_length,
+        max_message_bytes => args.max_message_bytes,
+        max_connections => args.max_connections,
+        max_connections_per_ip => args.max_connections_per_ip,
+        reconnect_cooldown => args.reconnect_cooldown.map(Duration::from_secs),
+        allow => args.allow,
+        deny => args.deny,
+        idle_timeout => Duration::from_secs(args.idle_timeout),
+        write_timeout => Duration::from_secs(args.write_timeout),
+        slow_write_threshold => args.slow_write_threshold,
+        tcp_nodelay => args.tcp_nodelay,
+        echo_self => args.echo_self,
+        loopback => args.loopback,
+        left_reason => args.left_reason,
+        number_per_sender => args.number_per_sender,
+        history_len => args.history_len,
+        history_ttl => Duration::from_secs(args.history_ttl),
+        outbound_queue_len => args.outbound_queue_len,
+        backpressure => args.backpressure,
+        client_ids => args.client_ids,
+        max_queued_bytes => args.max_queued_bytes,
+        broadcast_yield_every => args.broadcast_yield_every,
+        protocol => args.protocol,
+        framing => args.framing,
+        delimiters => args.delimiters,
+        websocket => args.websocket,
+        auth_token => args.auth_token,
+        admin_token => args.admin_token,
+        auth_timeout => Duration::from_secs(args.auth_timeout),
+        health_probe_window => args.health_probe_window,
+        max_handshakes => args.max_handshakes,
+        metrics_addr => args.metrics_addr,
+        log_throughput => args.log_throughput,
+        log_file => args.log_file,
+        drain_timeout => Duration::from_secs(args.drain_timeout),
+        shutdown_timeout => Duration::from_secs(args.shutdown_timeout),
+    }
+
+    // the rest are made up of more than one flag
+    if given("keepalive_interval") {
+        config.keepalive = args.keepalive_interval.map(|secs| Keepalive {
+            interval: Duration::from_secs(secs),
+            max_missed: args.keepalive_max_missed,
+        });
+    } else if let Some(keepalive) = config
+        .keepalive
+        .as_mut()
+        .filter(|_| given("keepalive_max_missed"))
+    {
+        keepalive.max_missed = args.keepalive_max_missed;
+    }
+
+    if given("tcp_keepalive_time") {
+        config.tcp_keepalive = args.tcp_keepalive_time.map(|secs| TcpKeepalive {
+            time: Duration::from_secs(secs),
+            interval: Duration::from_secs(args.tcp_keepalive_interval),
+            retries: args.tcp_keepalive_retries,
+        });
+    } else if let Some(keepalive) = config.tcp_keepalive.as_mut() {
+        if given("tcp_keepalive_interval") {
+            keepalive.interval = Duration::from_secs(args.tcp_keepalive_interval);
+        }
+        if given("tcp_keepalive_retries") {
+            keepalive.retries = args.tcp_keepalive_retries;
+        }
+    }
+
+    if let Some(path) = args.motd_file {
+        let motd = std::fs::read_to_string(&path)
+            .map_err(|source| ConfigFileError::Read { path, source })?;
+        config.motd = Some(motd);
+    }
+
+    if given("rate_limit") {
+        config.rate_limit = args.rate_limit.map(|per_second| RateLimit {
+            per_second,
+            burst: args.rate_limit_burst,
+        });
+    } else if let Some(rate_limit) = config
+        .rate_limit
+        .as_mut()
+        .filter(|_| given("rate_limit_burst"))
+    {
+        rate_limit.burst = args.rate_limit_burst;
+    }
+
+    if given("dedup_window") {
+        config.dedup = args.dedup_window.map(|window| Dedup {
+            window,
+            cache_size: args.dedup_cache_size,
+        });
+    } else if let Some(dedup) = config.dedup.as_mut().filter(|_| given("dedup_cache_size")) {
+        dedup.cache_size = args.dedup_cache_size;
+    }
+
+    // the two are required together
+    if given("tls_cert") {
+        config.tls = args
+            .tls_cert
+            .zip(args.tls_key)
+            .map(|(cert, key)| Tls { cert, key });
+    }
+
+    if given("login_prefix") {
+        config.prefixes.login = args.login_prefix;
+    }
+
+    if given("message_prefix") {
+        config.prefixes.message = args.message_prefix;
+    }
+
+    Ok(config)
+}
 
-    serve(args.bind).await
+/// fractional seconds, refusing anything a duration can't hold instead of panicking on it
+fn secs(arg: &str) -> Result<Duration, String> {
+    let secs = arg.parse::<f64>().map_err(|e| e.to_string())?;
+    Duration::try_from_secs_f64(secs).map_err(|e| e.to_string())
 }
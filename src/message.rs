@@ -0,0 +1,340 @@
+use std::{fmt, str::FromStr};
+
+use thiserror::Error;
+
+use crate::{config::Prefixes, protocol::Outgoing, server::ClientId};
+
+/// a line the server sends in the plain protocol, for clients written against the library
+///
+/// formatting and parsing assume the default [`Prefixes`] and text content,
+/// a server using length delimited framing may relay bytes that aren't utf-8
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// `LOGIN:<id> v<version>`, the greeting
+    Login { id: ClientId, version: u32 },
+    /// `JOIN:<id>`
+    Join { id: ClientId },
+    /// `LEFT:<id>`, with the reason after a space when the server is configured to say why
+    Left {
+        id: ClientId,
+        reason: Option<String>,
+    },
+    /// `MESSAGE:<seq>:<from> <sent_at> <content>`, `<from>#<sender seq>` when numbering per sender
+    Chat {
+        seq: u64,
+        from: String,
+        sender_seq: Option<u64>,
+        /// milliseconds since the unix epoch
+        sent_at: u128,
+        content: String,
+    },
+    /// `HISTORY:<seq>:<from> <sent_at> <content>`, said before the client joined
+    History {
+        seq: u64,
+        from: String,
+        sent_at: u128,
+        content: String,
+    },
+    /// `DM:<from> <content>`
+    Dm { from: String, content: String },
+    /// `MOTD:<line>`
+    Motd { line: String },
+    /// `PEERS:<name>,<name>`
+    Peers { peers: Vec<String> },
+    /// `STATS:<bytes in> <bytes out>`
+    Stats { bytes_in: u64, bytes_out: u64 },
+    /// `SELF:<id> nick=<nick> room=<room>`, without the nick until one is set
+    Identity {
+        id: ClientId,
+        nick: Option<String>,
+        room: String,
+    },
+    /// `OK:nick set`
+    NickSet,
+    /// `OK:tag set`
+    TagSet,
+    /// `OK:mode observer`
+    Observing,
+    /// `ACK:<count>`
+    Ack { count: usize },
+    /// `OK:compress on` or `OK:compress off`
+    Compression { enabled: bool },
+    /// `OK:joined <room>`
+    Joined { room: String },
+    /// `OK:hello v<version> <feature> <feature>`
+    Hello { version: u32, features: Vec<String> },
+    /// `OK:maxlen <max line length>`
+    MaxLen { max_line_length: usize },
+    /// `ERR:<reason>`
+    Err { reason: String },
+    /// `FULL`
+    Full,
+    /// `TOOMANY`
+    TooMany,
+    /// `PING`
+    Ping,
+    /// `KICKED:by admin`
+    Kicked,
+    /// `BYE:<reason>`
+    Bye { reason: String },
+    /// `SYSTEM:<content>`, from the host application
+    System { content: String },
+}
+
+/// the line isn't one the server sends
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("unrecognized line: {line}")]
+pub struct ParseMessageError {
+    line: String,
+}
+
+impl Message {
+    /// borrows the message as what the server encodes, so there's only the one formatter
+    fn with_outgoing<R>(&self, f: impl FnOnce(&Outgoing<'_>) -> R) -> R {
+        let features: Vec<&str>;
+
+        let outgoing = match self {
+            Message::Login { id, version } => Outgoing::Login {
+                id: *id,
+                version: *version,
+            },
+            Message::Join { id } => Outgoing::Join { id: *id },
+            Message::Left { id, reason } => Outgoing::Left {
+                id: *id,
+                reason: reason.as_deref(),
+            },
+            Message::Chat {
+                seq,
+                from,
+                sender_seq,
+                sent_at,
+                content,
+            } => Outgoing::Message {
+                seq: *seq,
+                from,
+                sender_seq: *sender_seq,
+                sent_at: *sent_at,
+                content: content.as_bytes(),
+            },
+            Message::History {
+                seq,
+                from,
+                sent_at,
+                content,
+            } => Outgoing::History {
+                seq: *seq,
+                from,
+                sent_at: *sent_at,
+                content: content.as_bytes(),
+            },
+            Message::Dm { from, content } => Outgoing::Dm { from, content },
+            Message::Motd { line } => Outgoing::Motd { line },
+            Message::Peers { peers } => Outgoing::Peers { peers },
+            Message::Stats {
+                bytes_in,
+                bytes_out,
+            } => Outgoing::Stats {
+                bytes_in: *bytes_in,
+                bytes_out: *bytes_out,
+            },
+            Message::Identity { id, nick, room } => Outgoing::Identity {
+                id: *id,
+                nick: nick.as_deref(),
+                room,
+            },
+            Message::NickSet => Outgoing::NickSet,
+            Message::TagSet => Outgoing::TagSet,
+            Message::Observing => Outgoing::Observing,
+            Message::Ack { count } => Outgoing::Ack { count: *count },
+            Message::Compression { enabled } => Outgoing::Compression { enabled: *enabled },
+            Message::Joined { room } => Outgoing::Joined { room },
+            Message::Hello {
+                version,
+                features: names,
+            } => {
+                features = names.iter().map(String::as_str).collect();
+                Outgoing::Hello {
+                    version: *version,
+                    features: &features,
+                }
+            }
+            Message::MaxLen { max_line_length } => Outgoing::MaxLen {
+                max_line_length: *max_line_length,
+            },
+            Message::Err { reason } => Outgoing::Err { reason },
+            Message::Full => Outgoing::Full,
+            Message::TooMany => Outgoing::TooMany,
+            Message::Ping => Outgoing::Ping,
+            Message::Kicked => Outgoing::Kicked,
+            Message::Bye { reason } => Outgoing::Bye { reason },
+            Message::System { content } => Outgoing::System { content },
+        };
+
+        f(&outgoing)
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = self.with_outgoing(|msg| msg.to_plain(&Prefixes::default()));
+        // every part of it came from a string
+        f.write_str(&String::from_utf8_lossy(&line))
+    }
+}
+
+impl FromStr for Message {
+    type Err = ParseMessageError;
+
+    fn from_str(line: &str) -> Result<Self, ParseMessageError> {
+        parse(line).ok_or_else(|| ParseMessageError {
+            line: line.to_owned(),
+        })
+    }
+}
+
+fn parse(line: &str) -> Option<Message> {
+    let msg = match line {
+        "OK:nick set" => Message::NickSet,
+        "OK:tag set" => Message::TagSet,
+        "OK:mode observer" => Message::Observing,
+        "OK:compress on" => Message::Compression { enabled: true },
+        "OK:compress off" => Message::Compression { enabled: false },
+        "FULL" => Message::Full,
+        "TOOMANY" => Message::TooMany,
+        "PING" => Message::Ping,
+        "KICKED:by admin" => Message::Kicked,
+        _ => {
+            let (kind, rest) = line.split_once(':')?;
+            parse_fields(kind, rest)?
+        }
+    };
+
+    Some(msg)
+}
+
+/// the lines that carry something after their `<kind>:`
+fn parse_fields(kind: &str, rest: &str) -> Option<Message> {
+    let msg = match kind {
+        "LOGIN" => {
+            let (id, version) = rest.split_once(" v")?;
+            Message::Login {
+                id: id.parse().ok()?,
+                version: version.parse().ok()?,
+            }
+        }
+        "JOIN" => Message::Join {
+            id: rest.parse().ok()?,
+        },
+        "LEFT" => match rest.split_once(' ') {
+            Some((id, reason)) => Message::Left {
+                id: id.parse().ok()?,
+                reason: Some(reason.to_owned()),
+            },
+            None => Message::Left {
+                id: rest.parse().ok()?,
+                reason: None,
+            },
+        },
+        "MESSAGE" | "HISTORY" => {
+            let (seq, rest) = rest.split_once(':')?;
+            let mut fields = rest.splitn(3, ' ');
+            let (from, sent_at, content) = (fields.next()?, fields.next()?, fields.next()?);
+            let (seq, from, sent_at, content) = (
+                seq.parse().ok()?,
+                from.to_owned(),
+                sent_at.parse().ok()?,
+                content.to_owned(),
+            );
+
+            if kind == "MESSAGE" {
+                // nicknames can't contain `#`
+                let (from, sender_seq) = match from.split_once('#') {
+                    Some((from, sender_seq)) => (from.to_owned(), Some(sender_seq.parse().ok()?)),
+                    None => (from, None),
+                };
+                Message::Chat {
+                    seq,
+                    from,
+                    sender_seq,
+                    sent_at,
+                    content,
+                }
+            } else {
+                Message::History {
+                    seq,
+                    from,
+                    sent_at,
+                    content,
+                }
+            }
+        }
+        "DM" => {
+            let (from, content) = rest.split_once(' ')?;
+            Message::Dm {
+                from: from.to_owned(),
+                content: content.to_owned(),
+            }
+        }
+        "MOTD" => Message::Motd {
+            line: rest.to_owned(),
+        },
+        "PEERS" => Message::Peers {
+            peers: rest
+                .split(',')
+                .filter(|peer| !peer.is_empty())
+                .map(str::to_owned)
+                .collect(),
+        },
+        "STATS" => {
+            let (bytes_in, bytes_out) = rest.split_once(' ')?;
+            Message::Stats {
+                bytes_in: bytes_in.parse().ok()?,
+                bytes_out: bytes_out.parse().ok()?,
+            }
+        }
+        "SELF" => {
+            let (id, rest) = rest.split_once(' ')?;
+            let (nick, room) = match rest.split_once(' ') {
+                Some((nick, room)) => (Some(nick.strip_prefix("nick=")?), room),
+                None => (None, rest),
+            };
+            Message::Identity {
+                id: id.parse().ok()?,
+                nick: nick.map(str::to_owned),
+                room: room.strip_prefix("room=")?.to_owned(),
+            }
+        }
+        "ACK" => Message::Ack {
+            count: rest.parse().ok()?,
+        },
+        "OK" => {
+            if let Some(room) = rest.strip_prefix("joined ") {
+                Message::Joined {
+                    room: room.to_owned(),
+                }
+            } else if let Some(max) = rest.strip_prefix("maxlen ") {
+                Message::MaxLen {
+                    max_line_length: max.parse().ok()?,
+                }
+            } else {
+                let mut words = rest.strip_prefix("hello v")?.split(' ');
+                Message::Hello {
+                    version: words.next()?.parse().ok()?,
+                    features: words.map(str::to_owned).collect(),
+                }
+            }
+        }
+        "ERR" => Message::Err {
+            reason: rest.to_owned(),
+        },
+        "BYE" => Message::Bye {
+            reason: rest.to_owned(),
+        },
+        "SYSTEM" => Message::System {
+            content: rest.to_owned(),
+        },
+        _ => return None,
+    };
+
+    Some(msg)
+}
@@ -0,0 +1,230 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, info, warn};
+
+use crate::transport::{self, AcceptFailure};
+
+/// how long a scraper gets to send its request and read the response
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// counters exposed in the prometheus text format
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    /// currently connected clients
+    pub(crate) connections: AtomicU64,
+    /// the most clients ever connected at once
+    pub(crate) peak_connections: AtomicU64,
+    /// clients ever let in, not counting those turned away
+    pub(crate) connections_total: AtomicU64,
+    /// frames received from clients
+    pub(crate) messages_total: AtomicU64,
+    /// bytes received from clients, not counting framing
+    pub(crate) bytes_total: AtomicU64,
+    /// chat messages broadcast, however many clients each reached
+    pub(crate) relayed_total: AtomicU64,
+    /// bytes waiting to be written across every client
+    pub(crate) queued_bytes: AtomicU64,
+    /// messages that never reached a client, by why
+    pub(crate) dropped_total: LabeledCounter,
+    /// clients that went away, by why
+    pub(crate) disconnects_total: LabeledCounter,
+}
+
+/// a counter per value of a single `reason` label
+///
+/// reasons are only ever string constants so there are never more than a handful of them
+#[derive(Debug, Default)]
+pub(crate) struct LabeledCounter(Mutex<BTreeMap<&'static str, u64>>);
+
+impl LabeledCounter {
+    pub(crate) fn inc(&self, reason: &'static str) {
+        let mut counts = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        *counts.entry(reason).or_default() += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let counts = self.0.lock().unwrap_or_else(|e| e.into_inner());
+
+        let _ = write!(out, "# HELP {name} {help}\n# TYPE {name} counter\n");
+        for (reason, count) in counts.iter() {
+            let _ = writeln!(out, "{name}{{reason=\"{reason}\"}} {count}");
+        }
+    }
+}
+
+impl Metrics {
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        for (name, kind, help, value) in [
+            (
+                "broadcast_connections",
+                "gauge",
+                "currently connected clients",
+                &self.connections,
+            ),
+            (
+                "broadcast_peak_connections",
+                "gauge",
+                "the most clients ever connected at once",
+                &self.peak_connections,
+            ),
+            (
+                "broadcast_connections_total",
+                "counter",
+                "clients ever let in",
+                &self.connections_total,
+            ),
+            (
+                "broadcast_messages_total",
+                "counter",
+                "frames received from clients",
+                &self.messages_total,
+            ),
+            (
+                "broadcast_bytes_total",
+                "counter",
+                "bytes received from clients",
+                &self.bytes_total,
+            ),
+            (
+                "broadcast_relayed_total",
+                "counter",
+                "chat messages broadcast",
+                &self.relayed_total,
+            ),
+            (
+                "broadcast_queued_bytes",
+                "gauge",
+                "bytes waiting to be written across every client",
+                &self.queued_bytes,
+            ),
+        ] {
+            let value = value.load(Ordering::Relaxed);
+            // writing to a string never fails
+            let _ = write!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+            );
+        }
+
+        self.dropped_total.render(
+            &mut out,
+            "broadcast_dropped_total",
+            "messages that never reached a client",
+        );
+        self.disconnects_total.render(
+            &mut out,
+            "broadcast_disconnects_total",
+            "clients that went away",
+        );
+
+        out
+    }
+}
+
+/// logs messages per second and the number of connections every second, never returns
+pub(crate) async fn log_throughput(metrics: &Metrics) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    let mut last_total = metrics.messages_total.load(Ordering::Relaxed);
+
+    loop {
+        interval.tick().await;
+
+        let total = metrics.messages_total.load(Ordering::Relaxed);
+        let connections = metrics.connections.load(Ordering::Relaxed);
+        let queued = metrics.queued_bytes.load(Ordering::Relaxed);
+
+        info!(
+            "{} messages/sec across {connections} connections, {queued} bytes queued",
+            total - last_total
+        );
+
+        last_total = total;
+    }
+}
+
+/// answers every http request on `listener` with the current metrics,
+/// only returns if the listener is broken for good
+pub(crate) async fn serve(listener: TcpListener, metrics: Arc<Metrics>) -> std::io::Result<()> {
+    if let Ok(addr) = listener.local_addr() {
+        info!("serving metrics on {addr}");
+    }
+
+    // how long the last pause was, zero while accepting works
+    let mut backoff = Duration::ZERO;
+
+    loop {
+        let (sock, addr) = match listener.accept().await {
+            Ok(accepted) => {
+                backoff = Duration::ZERO;
+                accepted
+            }
+            Err(e) => match AcceptFailure::of(&e) {
+                AcceptFailure::Connection => {
+                    debug!("error accepting metrics scrape: {e}");
+                    continue;
+                }
+                AcceptFailure::Exhausted => {
+                    backoff = transport::accept_backoff(backoff);
+                    warn!("accepting metrics scrapes failed, trying again in {backoff:?}: {e}");
+                    tokio::time::sleep(backoff).await;
+                    continue;
+                }
+                AcceptFailure::Fatal => return Err(e),
+            },
+        };
+
+        let metrics = Arc::clone(&metrics);
+
+        tokio::spawn(async move {
+            match tokio::time::timeout(SCRAPE_TIMEOUT, respond(sock, &metrics)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => debug!("error answering metrics scrape from {addr}: {e}"),
+                Err(_) => debug!("metrics scrape from {addr} timed out"),
+            }
+        });
+    }
+}
+
+async fn respond(mut sock: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+
+    // only the request line matters, read up to the end of the headers and ignore the rest
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < 8192 {
+        let n = sock.read(&mut buf).await?;
+
+        if n == 0 {
+            break;
+        }
+
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let response = if request.starts_with(b"GET /metrics ") {
+        let body = metrics.render();
+
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+    };
+
+    sock.write_all(response.as_bytes()).await?;
+    sock.shutdown().await
+}
@@ -0,0 +1,339 @@
+use std::{borrow::Cow, io::Write};
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{command::Command, config::Prefixes, server::ClientId};
+
+/// the protocol version clients are greeted with and may ask for in `HELLO`
+pub(crate) const VERSION: u32 = 1;
+
+/// optional parts of the protocol
+///
+/// clients that never send `HELLO` get all of them, those that do only get what they name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Features {
+    /// `COMPRESS on` and `COMPRESS off`
+    pub(crate) compress: bool,
+    /// `/join` and `/leave`
+    pub(crate) rooms: bool,
+    /// json encoded lines, only on offer when the server speaks [`Protocol::Json`]
+    pub(crate) json: bool,
+}
+
+impl Features {
+    /// everything the server supports speaking `protocol`
+    pub(crate) fn offered(protocol: Protocol) -> Self {
+        Features {
+            compress: true,
+            rooms: true,
+            json: protocol == Protocol::Json,
+        }
+    }
+
+    /// the `offered` features the client named,
+    /// names the server doesn't know are ignored so newer clients can still connect
+    pub(crate) fn negotiate<'a>(names: impl IntoIterator<Item = &'a str>, offered: Self) -> Self {
+        let mut features = Features {
+            compress: false,
+            rooms: false,
+            json: false,
+        };
+
+        for name in names {
+            match name {
+                "compress" => features.compress = offered.compress,
+                "rooms" => features.rooms = offered.rooms,
+                "json" => features.json = offered.json,
+                _ => {}
+            }
+        }
+
+        features
+    }
+
+    pub(crate) fn names(self) -> Vec<&'static str> {
+        [
+            (self.compress, "compress"),
+            (self.rooms, "rooms"),
+            (self.json, "json"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, name)| enabled.then_some(name))
+        .collect()
+    }
+
+    /// whether `command` belongs to a feature that wasn't negotiated
+    pub(crate) fn forbid(self, command: &Command<'_>) -> bool {
+        match command {
+            Command::Compress(_) => !self.compress,
+            Command::Join(_) | Command::Leave => !self.rooms,
+            _ => false,
+        }
+    }
+}
+
+/// how lines are encoded on the wire
+///
+/// a client that sends `COMPRESS on` gets everything after the `OK:compress on` reply
+/// as a 4 byte big endian length followed by that many bytes of raw deflate, whatever the framing,
+/// so from then on the stream it reads is binary until it sends `COMPRESS off`
+///
+/// the greeting carries the protocol version, e.g. `LOGIN:3 v1`,
+/// clients may answer `HELLO v1 compress rooms` to pin the version and the [`Features`] they use
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Protocol {
+    /// `MESSAGE:<seq>:<id> <sent_at> <content>` style text lines
+    #[default]
+    Plain,
+    /// one json object per line, e.g. `{"type":"message","from":"0",...}`
+    Json,
+}
+
+/// a line the server sends to clients
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Outgoing<'a> {
+    Login {
+        id: ClientId,
+        version: u32,
+    },
+    Join {
+        id: ClientId,
+    },
+    Left {
+        id: ClientId,
+        /// only when configured to say why
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<&'a str>,
+    },
+    Message {
+        /// counts up by one with every message in the room, starting over when the server does
+        seq: u64,
+        from: &'a str,
+        /// counts up by one with every message from the sender, only when configured
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sender_seq: Option<u64>,
+        /// milliseconds since the unix epoch
+        sent_at: u128,
+        /// arbitrary bytes with length delimited framing
+        #[serde(serialize_with = "serialize_lossy")]
+        content: &'a [u8],
+    },
+    /// a message sent before the client joined
+    History {
+        seq: u64,
+        from: &'a str,
+        /// milliseconds since the unix epoch
+        sent_at: u128,
+        #[serde(serialize_with = "serialize_lossy")]
+        content: &'a [u8],
+    },
+    Dm {
+        from: &'a str,
+        content: &'a str,
+    },
+    /// a line of the configured banner
+    Motd {
+        line: &'a str,
+    },
+    /// display names, with `#<tag>` after those of tagged clients
+    Peers {
+        peers: &'a [String],
+    },
+    /// what the requesting client has sent and received so far
+    Stats {
+        bytes_in: u64,
+        bytes_out: u64,
+    },
+    /// the requesting client as the server sees it
+    #[serde(rename = "self")]
+    Identity {
+        id: ClientId,
+        /// `None` until the client picks one
+        nick: Option<&'a str>,
+        room: &'a str,
+    },
+    NickSet,
+    TagSet,
+    /// the client is now an observer
+    Observing,
+    /// how many peers an `/ack-send` message was queued for, not counting any it was dropped for
+    Ack {
+        count: usize,
+    },
+    /// sent in the old encoding, everything after it is in the new one
+    Compression {
+        enabled: bool,
+    },
+    Joined {
+        room: &'a str,
+    },
+    /// the features out of those a client asked for in `HELLO` that it got
+    Hello {
+        version: u32,
+        features: &'a [&'a str],
+    },
+    /// what the client asked for, capped to what the server allows
+    MaxLen {
+        max_line_length: usize,
+    },
+    Err {
+        reason: &'a str,
+    },
+    Full,
+    TooMany,
+    Ping,
+    /// the client was disconnected by an admin
+    Kicked,
+    Bye {
+        reason: &'a str,
+    },
+    /// from the host application rather than any client
+    System {
+        content: &'a str,
+    },
+}
+
+impl Outgoing<'_> {
+    /// the plain text form
+    pub(crate) fn to_plain(&self, prefixes: &Prefixes) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        // writing to a vec never fails
+        let _ = match self {
+            Outgoing::Login { id, version } => write!(buf, "{}{id} v{version}", prefixes.login),
+            Outgoing::Join { id } => write!(buf, "JOIN:{id}"),
+            Outgoing::Left { id, reason: None } => write!(buf, "LEFT:{id}"),
+            Outgoing::Left {
+                id,
+                reason: Some(reason),
+            } => write!(buf, "LEFT:{id} {reason}"),
+            Outgoing::Message {
+                seq,
+                from,
+                sender_seq: None,
+                sent_at,
+                content,
+            } => write!(buf, "{}{seq}:{from} {sent_at} ", prefixes.message)
+                .and_then(|()| buf.write_all(content)),
+            Outgoing::Message {
+                seq,
+                from,
+                sender_seq: Some(sender_seq),
+                sent_at,
+                content,
+            } => write!(
+                buf,
+                "{}{seq}:{from}#{sender_seq} {sent_at} ",
+                prefixes.message
+            )
+            .and_then(|()| buf.write_all(content)),
+            Outgoing::History {
+                seq,
+                from,
+                sent_at,
+                content,
+            } => {
+                write!(buf, "HISTORY:{seq}:{from} {sent_at} ").and_then(|()| buf.write_all(content))
+            }
+            Outgoing::Dm { from, content } => write!(buf, "DM:{from} {content}"),
+            Outgoing::Motd { line } => write!(buf, "MOTD:{line}"),
+            Outgoing::Peers { peers } => write!(buf, "PEERS:{}", peers.join(",")),
+            Outgoing::Stats {
+                bytes_in,
+                bytes_out,
+            } => write!(buf, "STATS:{bytes_in} {bytes_out}"),
+            Outgoing::Identity { id, nick, room } => match nick {
+                Some(nick) => write!(buf, "SELF:{id} nick={nick} room={room}"),
+                None => write!(buf, "SELF:{id} room={room}"),
+            },
+            Outgoing::NickSet => write!(buf, "OK:nick set"),
+            Outgoing::TagSet => write!(buf, "OK:tag set"),
+            Outgoing::Observing => write!(buf, "OK:mode observer"),
+            Outgoing::Ack { count } => write!(buf, "ACK:{count}"),
+            Outgoing::Compression { enabled: true } => write!(buf, "OK:compress on"),
+            Outgoing::Compression { enabled: false } => write!(buf, "OK:compress off"),
+            Outgoing::Joined { room } => write!(buf, "OK:joined {room}"),
+            Outgoing::Hello {
+                version,
+                features: [],
+            } => write!(buf, "OK:hello v{version}"),
+            Outgoing::Hello { version, features } => {
+                write!(buf, "OK:hello v{version} {}", features.join(" "))
+            }
+            Outgoing::MaxLen { max_line_length } => write!(buf, "OK:maxlen {max_line_length}"),
+            Outgoing::Err { reason } => write!(buf, "ERR:{reason}"),
+            Outgoing::Full => write!(buf, "FULL"),
+            Outgoing::TooMany => write!(buf, "TOOMANY"),
+            Outgoing::Ping => write!(buf, "PING"),
+            Outgoing::Kicked => write!(buf, "KICKED:by admin"),
+            Outgoing::Bye { reason } => write!(buf, "BYE:{reason}"),
+            Outgoing::System { content } => write!(buf, "SYSTEM:{content}"),
+        };
+
+        buf
+    }
+}
+
+/// drops ascii control characters other than tab,
+/// so what a client sends can't end a line early and pass the rest off as the server's
+pub(crate) fn strip_controls(content: &[u8]) -> Cow<'_, [u8]> {
+    let is_control = |byte: &u8| byte.is_ascii_control() && *byte != b'\t';
+
+    if !content.iter().any(is_control) {
+        return Cow::Borrowed(content);
+    }
+
+    Cow::Owned(
+        content
+            .iter()
+            .copied()
+            .filter(|byte| !is_control(byte))
+            .collect(),
+    )
+}
+
+/// json only carries text, json clients only ever send text anyway
+fn serialize_lossy<S: Serializer>(content: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&String::from_utf8_lossy(content))
+}
+
+/// a frame sent by a client once the protocol specific encoding is stripped away
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Incoming {
+    Pong,
+    /// either a command or a chat message
+    Message(Bytes),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum JsonIncoming {
+    Pong,
+    Message { content: String },
+}
+
+impl Protocol {
+    /// `prefixes` only matter for plain text
+    pub(crate) fn encode(self, msg: &Outgoing, prefixes: &Prefixes) -> Bytes {
+        match self {
+            Protocol::Plain => msg.to_plain(prefixes).into(),
+            Protocol::Json => serde_json::to_vec(msg)
+                .expect("outgoing messages to always serialize")
+                .into(),
+        }
+    }
+
+    pub(crate) fn decode(self, frame: Bytes) -> Result<Incoming, serde_json::Error> {
+        match self {
+            Protocol::Plain if frame == "PONG" => Ok(Incoming::Pong),
+            Protocol::Plain => Ok(Incoming::Message(frame)),
+            Protocol::Json => match serde_json::from_slice(&frame)? {
+                JsonIncoming::Pong => Ok(Incoming::Pong),
+                JsonIncoming::Message { content } => Ok(Incoming::Message(content.into())),
+            },
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::codec::OutFrame;
+use tokio::sync::Notify;
+
+/// the bytes waiting across every queue sharing it, so many slow clients
+/// together can't buffer more than `max` no matter how short each queue is
+#[derive(Debug)]
+pub(crate) struct Budget {
+    queued: AtomicUsize,
+    /// unlimited when `None`
+    max: Option<usize>,
+    /// set whenever a frame is refused for going over
+    exhausted: AtomicBool,
+}
+
+impl Budget {
+    pub(crate) fn new(max: Option<usize>) -> Self {
+        Budget {
+            queued: AtomicUsize::new(0),
+            max,
+            exhausted: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// whether queueing `len` more bytes would go over the cap
+    fn exceeded_by(&self, len: usize) -> bool {
+        let exceeded = self.max.is_some_and(|max| self.queued() + len > max);
+
+        if exceeded {
+            self.exhausted.store(true, Ordering::Relaxed);
+        }
+
+        exceeded
+    }
+
+    /// whether anything was refused since last asked
+    pub(crate) fn take_exhausted(&self) -> bool {
+        self.exhausted.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// a bounded queue of frames from the event loop to a single writer task
+///
+/// unlike an mpsc channel the sending side can evict what's queued,
+/// which dropping the oldest frame on backpressure needs
+pub(crate) fn bounded(capacity: usize, budget: Arc<Budget>) -> (Sender, Receiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            frames: VecDeque::with_capacity(capacity),
+            bytes: 0,
+            closed: false,
+        }),
+        capacity,
+        budget,
+        readable: Notify::new(),
+        writable: Notify::new(),
+    });
+
+    (Sender(Arc::clone(&shared)), Receiver(shared))
+}
+
+struct Shared {
+    state: Mutex<State>,
+    capacity: usize,
+    budget: Arc<Budget>,
+    // there is only ever one task on either end, so `notify_one` leaving
+    // a permit behind is enough to never miss a wakeup
+    readable: Notify,
+    writable: Notify,
+}
+
+struct State {
+    frames: VecDeque<OutFrame>,
+    /// the length of everything in `frames`, also counted towards the budget
+    bytes: usize,
+    /// set once either end is dropped
+    closed: bool,
+}
+
+impl State {
+    fn push_back(&mut self, frame: OutFrame, budget: &Budget) {
+        self.bytes += frame.len();
+        budget.queued.fetch_add(frame.len(), Ordering::Relaxed);
+        self.frames.push_back(frame);
+    }
+
+    fn pop_front(&mut self, budget: &Budget) -> Option<OutFrame> {
+        let frame = self.frames.pop_front()?;
+        self.bytes -= frame.len();
+        budget.queued.fetch_sub(frame.len(), Ordering::Relaxed);
+        Some(frame)
+    }
+
+    fn clear(&mut self, budget: &Budget) {
+        self.frames.clear();
+        budget.queued.fetch_sub(self.bytes, Ordering::Relaxed);
+        self.bytes = 0;
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        // nothing panics while holding the lock, but don't make it everyone's problem if it does
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.readable.notify_one();
+        self.writable.notify_one();
+    }
+}
+
+/// the other end has gone away
+#[derive(Debug)]
+pub(crate) struct Closed;
+
+#[derive(Debug)]
+pub(crate) enum TryPushError {
+    /// the frame handed back since it wasn't queued
+    Full(OutFrame),
+    /// the frame would take every queue together over the budget
+    OverBudget,
+    Closed,
+}
+
+#[derive(Debug)]
+pub(crate) enum PushError {
+    /// the frame would take every queue together over the budget,
+    /// waiting on this queue alone wouldn't free it up so the frame is dropped
+    OverBudget,
+    Closed,
+}
+
+/// the event loop's end, closes the queue when dropped
+/// though whatever is already queued is still handed out
+pub(crate) struct Sender(Arc<Shared>);
+
+impl Sender {
+    pub(crate) fn try_push(&self, frame: OutFrame) -> Result<(), TryPushError> {
+        let mut state = self.0.lock();
+
+        if state.closed {
+            return Err(TryPushError::Closed);
+        }
+
+        if self.0.budget.exceeded_by(frame.len()) {
+            return Err(TryPushError::OverBudget);
+        }
+
+        if state.frames.len() >= self.0.capacity {
+            return Err(TryPushError::Full(frame));
+        }
+
+        state.push_back(frame, &self.0.budget);
+        drop(state);
+
+        self.0.readable.notify_one();
+        Ok(())
+    }
+
+    /// queues `frame` even when full by dropping the oldest frame, which is returned
+    pub(crate) fn push_evicting(&self, frame: OutFrame) -> Result<Option<OutFrame>, Closed> {
+        let mut state = self.0.lock();
+
+        if state.closed {
+            return Err(Closed);
+        }
+
+        let evicted = if state.frames.len() >= self.0.capacity {
+            state.pop_front(&self.0.budget)
+        } else {
+            None
+        };
+
+        state.push_back(frame, &self.0.budget);
+        drop(state);
+
+        self.0.readable.notify_one();
+        Ok(evicted)
+    }
+
+    /// waits for room to queue `frame`
+    pub(crate) async fn push(&self, mut frame: OutFrame) -> Result<(), PushError> {
+        loop {
+            match self.try_push(frame) {
+                Ok(()) => return Ok(()),
+                Err(TryPushError::Full(returned)) => frame = returned,
+                Err(TryPushError::OverBudget) => return Err(PushError::OverBudget),
+                Err(TryPushError::Closed) => return Err(PushError::Closed),
+            }
+
+            self.0.writable.notified().await;
+        }
+    }
+
+    /// bytes queued and not yet handed to the writer
+    pub(crate) fn queued_bytes(&self) -> usize {
+        self.0.lock().bytes
+    }
+
+    /// frames queued and not yet handed to the writer
+    pub(crate) fn queued_frames(&self) -> usize {
+        self.0.lock().frames.len()
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// the writer task's end, closes the queue and drops whatever is left when dropped
+pub(crate) struct Receiver(Arc<Shared>);
+
+impl Receiver {
+    /// `None` once the queue is closed and empty
+    pub(crate) async fn pop(&mut self) -> Option<OutFrame> {
+        loop {
+            {
+                let mut state = self.0.lock();
+
+                if let Some(frame) = state.pop_front(&self.0.budget) {
+                    drop(state);
+
+                    self.0.writable.notify_one();
+                    return Some(frame);
+                }
+
+                if state.closed {
+                    return None;
+                }
+            }
+
+            self.0.readable.notified().await;
+        }
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.0.close();
+        self.0.lock().clear(&self.0.budget);
+    }
+}
@@ -0,0 +1,37 @@
+use tokio::time::Instant;
+
+use crate::config::RateLimit;
+
+/// a token bucket, one token is spent per message
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// starts out full whatever the limit turns out to be
+    pub(crate) fn new() -> Self {
+        TokenBucket {
+            // clamped down to the burst size on the first refill
+            tokens: f64::INFINITY,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// returns false if the bucket is empty and the message should be rejected
+    pub(crate) fn try_take(&mut self, limit: RateLimit) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last_refill).as_secs_f64() * limit.per_second;
+
+        self.tokens = (self.tokens + refill).min(f64::from(limit.burst));
+        self.last_refill = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+        true
+    }
+}
@@ -1,38 +1,410 @@
-use std::pin::Pin;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, PoisonError, RwLock,
+    },
+    time::{Duration, SystemTime},
+};
 
-use futures::{stream::SelectAll, SinkExt, Stream, StreamExt};
+use bytes::Bytes;
+use futures::{future::BoxFuture, SinkExt, Stream, StreamExt};
+use ipnet::IpNet;
 use tokio::{
-    net::{TcpListener, TcpStream, ToSocketAddrs},
+    fs::File,
+    net::{TcpListener, UdpSocket, UnixListener},
     select,
+    signal::unix::{signal, Signal, SignalKind},
+    sync::{broadcast, mpsc, oneshot},
+    task::JoinSet,
+    time::{Instant, Interval},
 };
+use tokio_rustls::TlsAcceptor;
 
-use tokio_util::codec::{Framed, LinesCodec, LinesCodecError};
-use tracing::{debug, info, instrument, trace, Level};
+use tracing::{debug, error, info, instrument, trace, warn, Level};
 
+use serde::Serialize;
 use thiserror::Error;
 
+pub use crate::{
+    codec::FrameError,
+    message::{Message, ParseMessageError},
+};
+
+use crate::{
+    audit::AuditLog,
+    codec::{FrameCodec, OutFrame},
+    command::Command,
+    config::{ClientIds, Filtered, Framing, Prefixes, Protocol, ServerConfig, TcpKeepalive},
+    connection::{Connections, Outbound},
+    dedup::RecentMessages,
+    framed::{self, Reader, Writer},
+    history::{History, Recorded},
+    metrics::{self, Metrics},
+    protocol::{self, Features, Incoming, Outgoing},
+    tls,
+    transport::{self, AcceptFailure, Listener, Socket},
+    udp::Udp,
+};
+
 mod util {
-    pub const MAX_CODEC_LENGTH: usize = 8192;
+    /// upper bound on how often connections are checked for being idle
+    pub const MAX_IDLE_CHECK_PERIOD: std::time::Duration = std::time::Duration::from_secs(1);
+
+    pub const INVALID_NICK_REASON: &str = "invalid nick";
+    pub const INVALID_TAG_REASON: &str = "invalid tag";
+    pub const NO_SUCH_PEER_REASON: &str = "no such peer";
+    pub const INVALID_ROOM_REASON: &str = "invalid room";
+    pub const BAD_JSON_REASON: &str = "bad json";
+    pub const SHUTDOWN_REASON: &str = "server shutting down";
+    pub const UNAUTHORIZED_REASON: &str = "unauthorized";
+    pub const RATE_LIMITED_REASON: &str = "rate limited";
+    pub const BLOCKED_REASON: &str = "blocked";
+    pub const TOO_LONG_REASON: &str = "line too long";
+    pub const QUIT_REASON: &str = "quit";
+    pub const FORBIDDEN_REASON: &str = "forbidden";
+    pub const INVALID_LENGTH_REASON: &str = "invalid length";
+    pub const UNSUPPORTED_VERSION_REASON: &str = "unsupported version";
+    pub const SLOW_DOWN_REASON: &str = "slow down";
+    pub const MESSAGE_TOO_LONG_REASON: &str = "message too long";
+    pub const NOT_NEGOTIATED_REASON: &str = "not negotiated";
+    pub const READ_ONLY_REASON: &str = "read only";
+    pub const INVALID_MODE_REASON: &str = "invalid mode";
+    pub const ID_IN_USE_REASON: &str = "id in use";
+    /// what others are told in `LEFT` when a client becomes an observer
+    pub const OBSERVING_REASON: &str = "observing";
+    pub const CHANGED_ROOM_REASON: &str = "changed room";
+
+    /// the room every client starts out in
+    pub const DEFAULT_ROOM: &str = "global";
+
+    /// compares without bailing at the first difference so the time taken
+    /// doesn't give away how much of a token was right
+    pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+
+    /// milliseconds since the unix epoch, zero if the clock is set before it
+    pub fn unix_millis(time: std::time::SystemTime) -> u128 {
+        time.duration_since(std::time::UNIX_EPOCH)
+            .map(|since| since.as_millis())
+            .unwrap_or_default()
+    }
+}
+
+/// identifies a client for the lifetime of the server,
+/// by default this is never reused and later clients get larger ids, see [`ClientIds`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(transparent)]
+pub struct ClientId(u64);
+
+impl ClientId {
+    /// `counter` numbers the clients that `strategy` has nothing else for
+    fn derive(
+        strategy: &ClientIds,
+        addr: Option<SocketAddr>,
+        token: Option<&str>,
+        counter: &AtomicU64,
+    ) -> Self {
+        let next = || counter.fetch_add(1, Ordering::Relaxed);
+
+        ClientId(match (strategy, addr) {
+            (ClientIds::Custom(derive), addr) => derive.apply(addr, token),
+            (ClientIds::Port, Some(addr)) => u64::from(addr.port()),
+            (ClientIds::Port, None) | (ClientIds::Monotonic, _) => next(),
+        })
+    }
+}
 
-    pub fn build_login_msg(port: u16) -> String {
-        format!("LOGIN:{port}")
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
     }
+}
 
-    pub fn build_message_msg(port: u16, content: &str) -> String {
-        format!("MESSAGE:{port} {content}")
+impl FromStr for ClientId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(ClientId)
     }
 }
 
 #[derive(Debug)]
-struct Event {
-    kind: EventKind,
-    port: u16,
+enum Event {
+    // boxed since the framed halves dwarf every other variant
+    NewConnection(Box<Accepted>),
+    NewMessage(ClientId, Bytes),
+    /// the client sent a frame over the length limit, which was skipped
+    MessageTooLong(ClientId),
+    /// `None` in place of a datagram over the length limit
+    Datagram(SocketAddr, Option<Bytes>),
+    ClientDisconnected(ClientId, DisconnectReason),
+    /// time to ping every connection and drop the ones that stopped answering
+    Keepalive,
+    /// the host application has something to tell everyone
+    Announcement(String),
+    /// the host application wants to know who's connected
+    RosterQuery(oneshot::Sender<Vec<Peer>>),
+    /// draining started or ran out of time, the event loop checks whether it's done after every event
+    Draining,
+}
+
+/// what the event loop listens to from outside the server, besides clients
+struct Inbox {
+    announcements: mpsc::Receiver<String>,
+    roster_queries: mpsc::Receiver<oneshot::Sender<Vec<Peer>>>,
+    /// SIGTERM, which stops new connections but lets existing ones carry on for a while,
+    /// `None` unless the server was asked to handle it
+    terminate: Option<Signal>,
+    /// SIGHUP, which reloads `access` from `config_file`, `None` unless the server was asked to handle it
+    hangup: Option<Signal>,
+    config_file: Option<PathBuf>,
+    /// shared with the acceptor, which checks every new connection against it
+    access: Arc<RwLock<AccessList>>,
+    drain_timeout: Duration,
+    /// set once draining, when the clients still connected get cut off
+    drain_deadline: Option<Instant>,
+}
+
+impl Inbox {
+    fn draining(&self) -> bool {
+        self.drain_deadline.is_some()
+    }
+
+    /// swaps in the lists from the config file, keeping the old ones if it can't be loaded
+    fn reload(&self) {
+        let Some(path) = &self.config_file else {
+            warn!("got SIGHUP but there's no config file to reload");
+            return;
+        };
+
+        match ServerConfig::from_file(path) {
+            Ok(config) => {
+                info!(
+                    "reloaded {}, {} networks allowed and {} denied",
+                    path.display(),
+                    config.allow.len(),
+                    config.deny.len()
+                );
+                *self.access.write().unwrap_or_else(PoisonError::into_inner) = AccessList {
+                    allow: config.allow,
+                    deny: config.deny,
+                };
+            }
+            Err(e) => warn!("keeping the current allow and deny lists: {e}"),
+        }
+    }
 }
 
+/// who may connect, replaced as a whole on reload so connections already accepted aren't affected
 #[derive(Debug)]
-enum EventKind {
-    NewConnection(TcpStream),
-    NewMessage(String),
+struct AccessList {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl AccessList {
+    /// deny wins over allow, an empty allowlist lets everyone in
+    fn permits(&self, ip: IpAddr) -> bool {
+        // ipv4 clients of an ipv6 socket show up as ipv4 mapped addresses
+        let ip = ip.to_canonical();
+
+        !self.deny.iter().any(|net| net.contains(&ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip)))
+    }
+}
+
+/// whether `access` lets `ip` in right now, it may be reloaded at any time
+fn permitted(access: &RwLock<AccessList>, ip: IpAddr) -> bool {
+    access
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .permits(ip)
+}
+
+/// a connected client as of when the roster was asked for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub id: ClientId,
+    /// `None` until the client picks one
+    pub nick: Option<String>,
+    /// `None` unless the client sent `TAG <label>`
+    pub tag: Option<String>,
+    pub room: String,
+    /// `None` for unix socket peers
+    pub addr: Option<SocketAddr>,
+}
+
+/// asks a running server who's connected without going through the wire protocol
+///
+/// the event loop answers in between events, so a handle is cheap to clone
+/// and may be used from any thread or runtime
+#[derive(Debug, Clone)]
+pub struct RosterHandle {
+    queries: mpsc::Sender<oneshot::Sender<Vec<Peer>>>,
+}
+
+impl RosterHandle {
+    /// every connected client in no particular order, `None` once the server has stopped
+    ///
+    /// only a point in time snapshot, clients may have come and gone by the time it's read
+    pub async fn snapshot(&self) -> Option<Vec<Peer>> {
+        let (reply, answer) = oneshot::channel();
+        self.queries.send(reply).await.ok()?;
+        answer.await.ok()
+    }
+}
+
+/// something that happened on a running server, for host applications to log or analyse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    Joined {
+        id: ClientId,
+        /// `None` for unix socket peers
+        addr: Option<SocketAddr>,
+    },
+    Left {
+        id: ClientId,
+        /// as it would appear in `LEFT`, e.g. `idle`
+        reason: &'static str,
+    },
+    /// a chat message that was broadcast, after any filter had its say
+    Message {
+        id: ClientId,
+        room: String,
+        seq: u64,
+        content: Bytes,
+    },
+    /// this subscriber fell behind and missed this many events
+    Missed(u64),
+}
+
+/// what a server got through before it stopped, as returned by [`Server::run`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServeStats {
+    /// clients let in, not counting those turned away
+    pub connections_served: u64,
+    /// chat messages broadcast, however many clients each reached
+    pub messages_relayed: u64,
+    /// the most clients connected at once
+    pub peak_connections: u64,
+    pub uptime: Duration,
+}
+
+impl ServeStats {
+    fn new(metrics: &Metrics, uptime: Duration) -> Self {
+        ServeStats {
+            connections_served: metrics.connections_total.load(Ordering::Relaxed),
+            messages_relayed: metrics.relayed_total.load(Ordering::Relaxed),
+            peak_connections: metrics.peak_connections.load(Ordering::Relaxed),
+            uptime,
+        }
+    }
+}
+
+impl fmt::Display for ServeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "served {} clients, at most {} at once, and relayed {} messages in {:.1?}",
+            self.connections_served, self.peak_connections, self.messages_relayed, self.uptime
+        )
+    }
+}
+
+/// how many events a subscriber may fall behind by before it starts missing them
+const EVENT_BUFFER: usize = 1024;
+
+/// why a client is being disconnected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DisconnectReason {
+    /// the client closed the connection
+    Closed,
+    /// reading from the client failed, e.g. it sent something the codec couldn't make sense of
+    ReadError,
+    /// the client was silent for longer than the idle timeout
+    Idle,
+    /// the client stopped answering pings
+    Unresponsive,
+    /// the client couldn't be sent something, its queue was full or writing to it failed
+    SendFailed,
+    Quit,
+    Kicked,
+    /// the client asked for a protocol version the server doesn't speak
+    Unsupported,
+}
+
+impl DisconnectReason {
+    /// as it appears in `LEFT`, and as the `reason` label in the metrics
+    fn names(self) -> (&'static str, &'static str) {
+        match self {
+            DisconnectReason::Closed => ("closed", "closed"),
+            DisconnectReason::ReadError => ("read error", "read_error"),
+            DisconnectReason::Idle => ("idle", "idle"),
+            DisconnectReason::Unresponsive => ("unresponsive", "unresponsive"),
+            DisconnectReason::SendFailed => ("send failed", "send_failed"),
+            DisconnectReason::Quit => ("quit", "quit"),
+            DisconnectReason::Kicked => ("kicked", "kicked"),
+            DisconnectReason::Unsupported => ("unsupported version", "unsupported_version"),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        self.names().0
+    }
+
+    /// the `reason` label in the metrics
+    fn label(self) -> &'static str {
+        self.names().1
+    }
+}
+
+impl fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// why the server couldn't start, or stopped before being asked to
+#[derive(Error, Debug)]
+pub enum ServeError {
+    #[error("failed to bind {addr}: {source}")]
+    Bind {
+        addr: SocketAddr,
+        source: std::io::Error,
+    },
+    #[error("failed to bind {}: {source}", path.display())]
+    BindUnix {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// settings that contradict each other or can't work
+    #[error("invalid config: {0}")]
+    InvalidConfig(&'static str),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// for callers that only deal in io errors, the message keeps the context
+impl From<ServeError> for std::io::Error {
+    fn from(e: ServeError) -> Self {
+        match e {
+            ServeError::Io(e) => e,
+            ServeError::InvalidConfig(_) => {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string())
+            }
+            ServeError::Bind { ref source, .. } | ServeError::BindUnix { ref source, .. } => {
+                std::io::Error::new(source.kind(), e.to_string())
+            }
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -40,134 +412,1975 @@ pub enum EventError {
     #[error(transparent)]
     IoError(#[from] std::io::Error),
     #[error(transparent)]
-    CodecError(#[from] tokio_util::codec::LinesCodecError),
+    CodecError(#[from] FrameError),
+}
+
+/// what clients have told us about themselves and what they've said, kept alongside the connections
+#[derive(Debug)]
+struct Roster {
+    nicks: HashMap<ClientId, String>,
+    /// a label orthogonal to rooms, e.g. telling bots from humans
+    tags: HashMap<ClientId, String>,
+    rooms: HashMap<ClientId, String>,
+    /// dropped for a room once it's been empty a while so numbering starts over the next time it's used
+    history: History,
+    /// comings and goings and what's said, for anyone who subscribed through [`Server::events`]
+    events: broadcast::Sender<ServerEvent>,
+}
+
+impl Roster {
+    fn new(history: History, events: broadcast::Sender<ServerEvent>) -> Self {
+        Roster {
+            nicks: HashMap::new(),
+            tags: HashMap::new(),
+            rooms: HashMap::new(),
+            history,
+            events,
+        }
+    }
+
+    /// only builds the event when someone is listening
+    fn publish(&self, event: impl FnOnce() -> ServerEvent) {
+        if self.events.receiver_count() > 0 {
+            let _ = self.events.send(event());
+        }
+    }
+
+    /// the nickname a client picked, or its id if it never set one
+    fn display_name(&self, id: ClientId) -> String {
+        self.nicks
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| id.to_string())
+    }
+
+    /// how `/list` shows a client, with `#<tag>` after the name of tagged ones
+    fn listed_name(&self, id: ClientId) -> String {
+        match self.tags.get(&id) {
+            Some(tag) => format!("{}#{tag}", self.display_name(id)),
+            None => self.display_name(id),
+        }
+    }
+
+    fn room(&self, id: ClientId) -> &str {
+        self.rooms
+            .get(&id)
+            .map_or(util::DEFAULT_ROOM, String::as_str)
+    }
+
+    /// moves a client to `room`, back to the default room when `None`
+    fn set_room(&mut self, id: ClientId, room: Option<String>) {
+        let left = match room {
+            Some(room) => {
+                self.history.occupied(&room);
+                self.rooms.insert(id, room)
+            }
+            None => self.rooms.remove(&id),
+        };
+
+        self.forget_if_empty(left);
+    }
+
+    /// numbers the next message sent in the client's room
+    ///
+    /// numbers are per room so everyone in a room sees them without gaps,
+    /// bar their own messages when those aren't echoed back
+    fn next_seq(&mut self, id: ClientId) -> u64 {
+        let room = self
+            .rooms
+            .get(&id)
+            .map_or(util::DEFAULT_ROOM, String::as_str);
+
+        self.history.next_seq(room)
+    }
+
+    fn remove(&mut self, id: ClientId) {
+        self.nicks.remove(&id);
+        self.tags.remove(&id);
+        let left = self.rooms.remove(&id);
+
+        self.forget_if_empty(left);
+    }
+
+    /// the default room is never forgotten, clients without a room are in it
+    fn forget_if_empty(&mut self, room: Option<String>) {
+        if let Some(room) = room.filter(|room| {
+            room != util::DEFAULT_ROOM && !self.rooms.values().any(|other| other == room)
+        }) {
+            self.history.emptied(&room);
+        }
+    }
+}
+
+/// moves a client to `room`, or back to the default room when `None`,
+/// telling those in the room it leaves with `LEFT` and those in the one it joins with `JOIN`
+async fn change_room(
+    id: ClientId,
+    room: Option<String>,
+    conns: &mut Connections,
+    roster: &mut Roster,
+) {
+    let old = roster.room(id).to_owned();
+    let new = room.as_deref().unwrap_or(util::DEFAULT_ROOM);
+    info!("client {id} moved from room {old} to {new}");
+
+    let left = Outgoing::Left {
+        id,
+        reason: conns.left_reason.then_some(util::CHANGED_ROOM_REASON),
+    };
+    let mut failed = conns
+        .broadcast(&left, |to| to != id && roster.room(to) == old)
+        .await;
+    failed.extend(
+        conns
+            .broadcast(&Outgoing::Join { id }, |to| {
+                to != id && roster.room(to) == new
+            })
+            .await,
+    );
+
+    roster.set_room(id, room);
+    disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+}
+
+/// sends a client what was said in its room before it got there,
+/// dropping it if that fails
+async fn replay_history(conns: &mut Connections, roster: &mut Roster, id: ClientId) {
+    let replay = roster
+        .history
+        .iter(roster.room(id))
+        .map(|recorded| Outgoing::History {
+            seq: recorded.seq,
+            from: &recorded.from,
+            sent_at: recorded.sent_at,
+            content: &recorded.content,
+        });
+
+    let mut failed = None;
+    for msg in replay {
+        failed = conns.send_to(id, &msg).await;
+        if failed.is_some() {
+            break;
+        }
+    }
+
+    disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+}
+
+/// removes the given connections and tells everyone else they left,
+/// any connection that fails to receive the notice is dropped in turn
+async fn disconnect(
+    conns: &mut Connections,
+    roster: &mut Roster,
+    ids: impl IntoIterator<Item = ClientId>,
+    reason: DisconnectReason,
+) {
+    let mut ids: Vec<_> = ids.into_iter().map(|id| (id, reason)).collect();
+
+    while let Some((id, reason)) = ids.pop() {
+        // remove first so we never try to write to the dead socket
+        let Some(connection) = conns.remove(id) else {
+            continue;
+        };
+
+        conns.metrics.disconnects_total.inc(reason.label());
+
+        let tag = roster.tags.get(&id).cloned();
+        // only the room the client was in hears it left
+        let room = roster.room(id).to_owned();
+        roster.remove(id);
+        roster.publish(|| ServerEvent::Left {
+            id,
+            reason: reason.as_str(),
+        });
+
+        info!(
+            client = %id,
+            addr = ?connection.addr,
+            tag,
+            bytes_in = connection.bytes_in,
+            bytes_out = connection.bytes_out(),
+            connections = conns.len(),
+            %reason,
+            "client {id} disconnected ({reason}), {} left",
+            conns.len()
+        );
+
+        // observers were announced as gone when they started observing
+        if connection.observer {
+            continue;
+        }
+
+        let left = Outgoing::Left {
+            id,
+            reason: conns.left_reason.then_some(reason.as_str()),
+        };
+        let failed = conns.broadcast(&left, |to| roster.room(to) == room).await;
+        ids.extend(
+            failed
+                .into_iter()
+                .map(|id| (id, DisconnectReason::SendFailed)),
+        );
+    }
 }
 
-struct FramedStream {
-    inner: Framed<TcpStream, LinesCodec>,
-    port: u16,
+/// length prefixed frames carry arbitrary bytes and can't be split by what's in them,
+/// nor can delimited frames, which never hold a delimiter, and both are relayed as is
+///
+/// lines lose their control characters so one can't pass for another, e.g. a fake `LOGIN:`
+fn sanitize<'a>(msg: &'a [u8], config: &ServerConfig) -> Cow<'a, [u8]> {
+    if config.framing != Framing::Lines || config.websocket {
+        Cow::Borrowed(msg)
+    } else {
+        protocol::strip_controls(msg)
+    }
 }
 
-impl Stream for FramedStream {
-    type Item = Result<(u16, String), LinesCodecError>;
+/// acts on a line a client sent, either a command or a chat message to relay
+async fn handle_message(
+    id: ClientId,
+    msg: &[u8],
+    conns: &mut Connections,
+    roster: &mut Roster,
+    audit: Option<&AuditLog>,
+    config: &ServerConfig,
+) {
+    let msg = &*sanitize(msg, config);
+
+    // frames that aren't valid text can't be commands, they're broadcast as is
+    let command = std::str::from_utf8(msg).ok().and_then(Command::parse);
+
+    if let Some(command) = &command {
+        if conns
+            .get(id)
+            .is_some_and(|conn| conn.features.forbid(command))
+        {
+            debug!("client {id} sent {command:?} without negotiating it");
+
+            let reply = Outgoing::Err {
+                reason: util::NOT_NEGOTIATED_REASON,
+            };
+            let failed = conns.send_to(id, &reply).await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+            return;
+        }
+    }
+
+    // quitting is the one thing observers may still do
+    if !matches!(command, Some(Command::Quit)) && conns.get(id).is_some_and(|conn| conn.observer) {
+        debug!("observer {id} tried to send something");
+
+        let reply = Outgoing::Err {
+            reason: util::READ_ONLY_REASON,
+        };
+        let failed = conns.send_to(id, &reply).await;
+        disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        return;
+    }
+
+    match command {
+        Some(Command::Nick(nick)) => {
+            // nicknames show up in place of the id so keep them to a single word,
+            // `#` is what follows a name with a tag in `PEERS` or its number in `MESSAGE`
+            // and `,` is what separates the names in `PEERS`
+            let reply = if nick.is_empty()
+                || nick.contains(|c: char| c.is_whitespace() || c == '#' || c == ',')
+            {
+                Outgoing::Err {
+                    reason: util::INVALID_NICK_REASON,
+                }
+            } else {
+                info!("client {id} is now known as {nick}");
+                roster.nicks.insert(id, nick.to_owned());
+                Outgoing::NickSet
+            };
+
+            let failed = conns.send_to(id, &reply).await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        }
+        Some(Command::List) => {
+            let peers: Vec<_> = conns
+                .iter()
+                .filter(|(peer, connection)| *peer != id && !connection.observer)
+                .map(|(peer, _)| roster.listed_name(peer))
+                .collect();
+
+            let failed = conns.send_to(id, &Outgoing::Peers { peers: &peers }).await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        }
+        // with auth on who's backing up is for admins to know
+        Some(Command::ListQueues)
+            if config.auth_token.is_some()
+                && !conns.get(id).is_some_and(|connection| connection.admin) =>
+        {
+            debug!("client {id} isn't allowed to see queues");
+
+            let reply = Outgoing::Err {
+                reason: util::FORBIDDEN_REASON,
+            };
+            let failed = conns.send_to(id, &reply).await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        }
+        Some(Command::ListQueues) => {
+            let peers: Vec<_> = conns
+                .iter()
+                .filter(|(peer, connection)| *peer != id && !connection.observer)
+                .map(|(peer, connection)| {
+                    format!(
+                        "{} q={} buf={}",
+                        roster.listed_name(peer),
+                        connection.queued_frames(),
+                        connection.queued_bytes()
+                    )
+                })
+                .collect();
+
+            let failed = conns.send_to(id, &Outgoing::Peers { peers: &peers }).await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        }
+        Some(Command::Tag(tag)) => {
+            // like nicknames they show up in `/list` and `PEERS`, so a single word without `#` or `,`
+            let reply = if tag.is_empty()
+                || tag.contains(|c: char| c.is_whitespace() || c == '#' || c == ',')
+            {
+                Outgoing::Err {
+                    reason: util::INVALID_TAG_REASON,
+                }
+            } else {
+                info!(client = %id, tag, "client {id} is tagged {tag}");
+                roster.tags.insert(id, tag.to_owned());
+                Outgoing::TagSet
+            };
+
+            let failed = conns.send_to(id, &reply).await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        }
+        Some(Command::Mode("observer")) => {
+            info!(client = %id, "client {id} is now an observer");
+
+            if let Some(connection) = conns.get_mut(id) {
+                connection.observer = true;
+            }
+
+            let failed = conns.send_to(id, &Outgoing::Observing).await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+
+            // as far as everyone else is concerned the client is gone, it isn't announced again when it really goes
+            let left = Outgoing::Left {
+                id,
+                reason: conns.left_reason.then_some(util::OBSERVING_REASON),
+            };
+            let room = roster.room(id);
+            let failed = conns
+                .broadcast(&left, |to| to != id && roster.room(to) == room)
+                .await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        }
+        Some(Command::Mode(_)) => {
+            let reply = Outgoing::Err {
+                reason: util::INVALID_MODE_REASON,
+            };
+            let failed = conns.send_to(id, &reply).await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        }
+        Some(Command::MsgTag { tag, text }) => {
+            relay(id, text.as_bytes(), Some(tag), conns, roster, audit, config).await;
+        }
+        Some(Command::Stats) => {
+            let Some(connection) = conns.get(id) else {
+                return;
+            };
+
+            let reply = Outgoing::Stats {
+                bytes_in: connection.bytes_in,
+                bytes_out: connection.bytes_out(),
+            };
+            let failed = conns.send_to(id, &reply).await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        }
+        Some(Command::Msg { to, text }) => {
+            let target = to
+                .parse()
+                .ok()
+                .filter(|to| *to != id && conns.contains(*to));
+
+            let failed = match target {
+                Some(to) => {
+                    let from = roster.display_name(id);
+                    conns
+                        .send_to(
+                            to,
+                            &Outgoing::Dm {
+                                from: &from,
+                                content: text,
+                            },
+                        )
+                        .await
+                }
+                None => {
+                    conns
+                        .send_to(
+                            id,
+                            &Outgoing::Err {
+                                reason: util::NO_SUCH_PEER_REASON,
+                            },
+                        )
+                        .await
+                }
+            };
+
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        }
+        Some(Command::Join(room)) => {
+            if room.is_empty() || room.contains(char::is_whitespace) {
+                let reply = Outgoing::Err {
+                    reason: util::INVALID_ROOM_REASON,
+                };
+                let failed = conns.send_to(id, &reply).await;
+                disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+                return;
+            }
+
+            // nobody is told anything and the history isn't replayed again
+            if roster.room(id) == room {
+                let failed = conns.send_to(id, &Outgoing::Joined { room }).await;
+                disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+                return;
+            }
+
+            change_room(id, Some(room.to_owned()), conns, roster).await;
+
+            match conns.send_to(id, &Outgoing::Joined { room }).await {
+                Some(failed) => {
+                    disconnect(conns, roster, [failed], DisconnectReason::SendFailed).await
+                }
+                None => replay_history(conns, roster, id).await,
+            }
+        }
+        Some(Command::Leave) => {
+            if roster.room(id) != util::DEFAULT_ROOM {
+                change_room(id, None, conns, roster).await;
+            }
+
+            let reply = Outgoing::Joined {
+                room: util::DEFAULT_ROOM,
+            };
+            let failed = conns.send_to(id, &reply).await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        }
+        Some(Command::Whoami) => {
+            let reply = Outgoing::Identity {
+                id,
+                nick: roster.nicks.get(&id).map(String::as_str),
+                room: roster.room(id),
+            };
+            let failed = conns.send_to(id, &reply).await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        }
+        Some(Command::Kick(target)) => {
+            if !conns.get(id).is_some_and(|connection| connection.admin) {
+                debug!("client {id} isn't allowed to kick");
+
+                let reply = Outgoing::Err {
+                    reason: util::FORBIDDEN_REASON,
+                };
+                let failed = conns.send_to(id, &reply).await;
+                disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+                return;
+            }
+
+            let Some(target) = target
+                .parse()
+                .ok()
+                .filter(|target| *target != id && conns.contains(*target))
+            else {
+                let reply = Outgoing::Err {
+                    reason: util::NO_SUCH_PEER_REASON,
+                };
+                let failed = conns.send_to(id, &reply).await;
+                disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+                return;
+            };
+
+            info!("client {target} kicked by {id}");
+
+            // like quitting, the writer finishes what's queued after the connection is removed
+            let _ = conns.send_to(target, &Outgoing::Kicked).await;
+            disconnect(conns, roster, [target], DisconnectReason::Kicked).await;
+        }
+        Some(Command::Compress(enabled)) => {
+            debug!(
+                "client {id} turned compression {}",
+                if enabled { "on" } else { "off" }
+            );
+
+            // the reply still goes out the way the client last asked for
+            let failed = conns.send_to(id, &Outgoing::Compression { enabled }).await;
+            if let Some(connection) = conns.get_mut(id) {
+                connection.compress = enabled;
+            }
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        }
+        Some(Command::MaxLen(requested)) => {
+            let reply = match requested.parse::<usize>() {
+                Ok(requested) if requested > 0 => {
+                    let max_line_length = requested.min(config.max_line_length);
+                    debug!("client {id} set its max line length to {max_line_length}");
+
+                    if let Some(connection) = conns.get_mut(id) {
+                        connection.max_line_length = max_line_length;
+                    }
+                    Outgoing::MaxLen { max_line_length }
+                }
+                _ => Outgoing::Err {
+                    reason: util::INVALID_LENGTH_REASON,
+                },
+            };
+
+            let failed = conns.send_to(id, &reply).await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        }
+        Some(Command::Hello { version, features }) => {
+            if version.strip_prefix('v').and_then(|v| v.parse().ok()) != Some(protocol::VERSION) {
+                info!("client {id} asked for unsupported protocol version {version}");
+
+                // like quitting, the writer finishes what's queued after the connection is removed
+                let _ = conns
+                    .send_to(
+                        id,
+                        &Outgoing::Err {
+                            reason: util::UNSUPPORTED_VERSION_REASON,
+                        },
+                    )
+                    .await;
+                disconnect(conns, roster, [id], DisconnectReason::Unsupported).await;
+                return;
+            }
+
+            let features = Features::negotiate(
+                features.split_whitespace(),
+                Features::offered(config.protocol),
+            );
+            debug!("client {id} negotiated {features:?}");
+
+            if let Some(connection) = conns.get_mut(id) {
+                connection.features = features;
+            }
+
+            let reply = Outgoing::Hello {
+                version: protocol::VERSION,
+                features: &features.names(),
+            };
+            let failed = conns.send_to(id, &reply).await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        }
+        Some(Command::Quit) => {
+            info!("client {id} quit");
+
+            // removing the connection lets the writer finish what's queued, goodbye included
+            let _ = conns
+                .send_to(
+                    id,
+                    &Outgoing::Bye {
+                        reason: util::QUIT_REASON,
+                    },
+                )
+                .await;
+            disconnect(conns, roster, [id], DisconnectReason::Quit).await;
+        }
+        Some(Command::AckSend(text)) => {
+            if let Some(count) =
+                relay(id, text.as_bytes(), None, conns, roster, audit, config).await
+            {
+                let failed = conns.send_to(id, &Outgoing::Ack { count }).await;
+                disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+            }
+        }
+        None => {
+            relay(id, msg, None, conns, roster, audit, config).await;
+        }
+    }
+}
+
+/// numbers the client's next message, `None` unless configured to
+fn next_sender_seq(id: ClientId, conns: &mut Connections, config: &ServerConfig) -> Option<u64> {
+    if !config.number_per_sender {
+        return None;
+    }
+
+    let connection = conns.get_mut(id)?;
+    connection.relayed += 1;
+    Some(connection.relayed - 1)
+}
+
+/// why a chat message wasn't relayed
+#[derive(Debug, Clone, Copy)]
+enum Refused {
+    TooLong,
+    Duplicate,
+    Filtered,
+}
+
+impl Refused {
+    /// what it's counted as in `dropped_total`
+    fn label(self) -> &'static str {
+        match self {
+            Refused::TooLong => "message_too_long",
+            Refused::Duplicate => "duplicate",
+            Refused::Filtered => "filtered",
+        }
+    }
+
+    /// what the sender is told, repeats are dropped without a word
+    fn reason(self) -> Option<&'static str> {
+        match self {
+            Refused::TooLong => Some(util::MESSAGE_TOO_LONG_REASON),
+            Refused::Duplicate => None,
+            Refused::Filtered => Some(util::BLOCKED_REASON),
+        }
+    }
+}
+
+/// holds a chat message to the limits everything relayed is held to,
+/// whether it came from a client or a datagram, and returns what to relay in its place
+fn screen<'a>(
+    msg: &'a [u8],
+    recent: &mut RecentMessages,
+    config: &ServerConfig,
+) -> Result<Cow<'a, [u8]>, Refused> {
+    if config.max_message_bytes.is_some_and(|max| msg.len() > max) {
+        return Err(Refused::TooLong);
+    }
+
+    if config
+        .dedup
+        .is_some_and(|dedup| recent.is_duplicate(msg, dedup))
+    {
+        return Err(Refused::Duplicate);
+    }
+
+    let filtered = config.filter.as_ref().map_or(Filtered::Pass, |filter| {
+        filter.apply(&String::from_utf8_lossy(msg))
+    });
+
+    match filtered {
+        Filtered::Pass => Ok(Cow::Borrowed(msg)),
+        Filtered::Rewrite(rewritten) => Ok(Cow::Owned(rewritten.into_bytes())),
+        Filtered::Reject => Err(Refused::Filtered),
+    }
+}
+
+/// broadcasts a chat message to the sender's room and records it,
+/// returns how many peers it was queued for or `None` if it was refused
+///
+/// with a `tag` only the room's members with that tag get it, and since the rest of the room
+/// never sees it it's kept out of the room's numbering and history
+async fn relay(
+    id: ClientId,
+    msg: &[u8],
+    tag: Option<&str>,
+    conns: &mut Connections,
+    roster: &mut Roster,
+    audit: Option<&AuditLog>,
+    config: &ServerConfig,
+) -> Option<usize> {
+    let connection = conns.get_mut(id)?;
+    let content = match screen(msg, &mut connection.recent, config) {
+        Ok(content) => content,
+        Err(refused) => {
+            debug!("not relaying message from {id}: {refused:?}");
+            conns.metrics.dropped_total.inc(refused.label());
+
+            if let Some(reason) = refused.reason() {
+                let failed = conns.send_to(id, &Outgoing::Err { reason }).await;
+                disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+            }
+            return None;
+        }
+    };
+    let content = &*content;
+
+    let room = roster.room(id).to_owned();
+    // carries the number of the room's next message, so nobody sees a gap
+    let seq = match tag {
+        Some(_) => roster.history.peek_seq(&room),
+        None => roster.next_seq(id),
+    };
+    let from = roster.display_name(id);
+    let sent_at = util::unix_millis(SystemTime::now());
+    let msg = Outgoing::Message {
+        seq,
+        from: &from,
+        sender_seq: next_sender_seq(id, conns, config),
+        sent_at,
+        content,
+    };
+    // the sender's own echo isn't counted
+    let mut peers = 0;
+    let failed = conns
+        .broadcast_with(
+            &msg,
+            |to| {
+                if config.loopback {
+                    return to == id;
+                }
+                (config.echo_self || to != id)
+                    && roster.room(to) == room
+                    && tag.is_none_or(|tag| roster.tags.get(&to).is_some_and(|to| to == tag))
+            },
+            |to| peers += usize::from(to != id),
+        )
+        .await;
+    conns.metrics.relayed_total.fetch_add(1, Ordering::Relaxed);
+
+    if let Some(audit) = audit {
+        audit.record(&room, seq, id, sent_at, content);
+    }
+
+    roster.publish(|| ServerEvent::Message {
+        id,
+        room: room.clone(),
+        seq,
+        content: Bytes::copy_from_slice(content),
+    });
 
-    fn poll_next(
-        mut self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Option<Self::Item>> {
-        Pin::new(&mut self.inner)
-            .poll_next(cx)
-            .map_ok(|msg| (self.port, msg))
+    // replaying a tagged message would reach everyone who joins
+    if tag.is_none() {
+        roster.history.push(
+            &room,
+            Recorded {
+                seq,
+                from,
+                sent_at,
+                content: Bytes::copy_from_slice(content),
+            },
+        );
     }
+
+    disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+
+    Some(peers)
 }
 
-#[instrument(level = Level::DEBUG, skip(conns), ret, err(level = Level::ERROR))]
-async fn handle_event(event: Event, conns: &mut SelectAll<FramedStream>) -> Result<(), EventError> {
-    match event.kind {
-        EventKind::NewConnection(sock) => {
-            let codec = LinesCodec::new_with_max_length(util::MAX_CODEC_LENGTH);
-            let mut framed = Framed::new(sock, codec);
-            framed.send(util::build_login_msg(event.port)).await?;
+#[instrument(level = Level::DEBUG, skip(conns, roster, audit, config, metrics, udp), fields(connections = conns.len()), ret, err(level = Level::ERROR))]
+async fn handle_event(
+    event: Event,
+    conns: &mut Connections,
+    roster: &mut Roster,
+    audit: Option<&AuditLog>,
+    config: &ServerConfig,
+    metrics: &Metrics,
+    udp: Option<&mut Udp>,
+) -> Result<(), EventError> {
+    match event {
+        Event::NewConnection(accepted) => {
+            let Accepted {
+                id,
+                addr,
+                admin,
+                reader,
+                writer: mut sink,
+            } = *accepted;
+
+            let rejection = if conns.contains(id) {
+                info!("rejecting client {id}, another client has the same id");
+                Some(Outgoing::Err {
+                    reason: util::ID_IN_USE_REASON,
+                })
+            } else if config.max_connections.is_some_and(|max| conns.len() >= max) {
+                info!("rejecting client {id}, server is full");
+                Some(Outgoing::Full)
+            } else if let Some(ip) = addr.map(|addr| addr.ip()).filter(|ip| {
+                config
+                    .max_connections_per_ip
+                    .is_some_and(|max| conns.count_from(*ip) >= max)
+            }) {
+                info!("rejecting client {id}, too many connections from {ip}");
+                Some(Outgoing::TooMany)
+            } else if let Some(ip) = addr
+                .map(|addr| addr.ip())
+                .filter(|ip| conns.connected_recently(*ip))
+            {
+                info!("rejecting client {id}, {ip} reconnected too soon");
+                Some(Outgoing::Err {
+                    reason: util::SLOW_DOWN_REASON,
+                })
+            } else {
+                None
+            };
+
+            if let Some(rejection) = rejection {
+                let msg = config.protocol.encode(&rejection, &config.prefixes);
+
+                // dropping the sink closes the socket
+                tokio::spawn(async move {
+                    let _ = sink.send(OutFrame::Plain(msg)).await;
+                });
+
+                return Ok(());
+            }
+
+            // the writer needs a way to end the reader, but the reader ends up in `conns`
+            let (reader, handle) = futures::stream::abortable(reader);
+            let outbound = Outbound::spawn(
+                id,
+                addr,
+                sink,
+                conns.budget(),
+                Arc::clone(&conns.metrics),
+                config,
+                handle.clone(),
+            );
+            conns.insert(id, addr, admin, (reader, handle), outbound);
+            metrics.connections_total.fetch_add(1, Ordering::Relaxed);
+            roster.publish(|| ServerEvent::Joined { id, addr });
+
+            info!(
+                client = %id,
+                connections = conns.len(),
+                "client {id} connected, {} now",
+                conns.len()
+            );
+
+            let mut failed = conns
+                .send_to(
+                    id,
+                    &Outgoing::Login {
+                        id,
+                        version: protocol::VERSION,
+                    },
+                )
+                .await;
+
+            // a frame per line, a newline in the middle of one would end it early for line based clients
+            for line in config.motd.iter().flat_map(|motd| motd.lines()) {
+                if failed.is_some() {
+                    break;
+                }
+                failed = conns.send_to(id, &Outgoing::Motd { line }).await;
+            }
+
+            // replayed before anyone else hears of the client so nothing live can come first
+            match failed {
+                Some(failed) => {
+                    disconnect(conns, roster, [failed], DisconnectReason::SendFailed).await
+                }
+                None => replay_history(conns, roster, id).await,
+            }
+
+            // everyone starts out in the default room, only those in it hear of the client
+            let failed = conns
+                .broadcast(&Outgoing::Join { id }, |to| {
+                    to != id && roster.room(to) == util::DEFAULT_ROOM
+                })
+                .await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        }
+        Event::MessageTooLong(id) => {
+            debug!("client {id} sent a frame over the length limit");
+            metrics.dropped_total.inc("too_long");
+
+            let failed = conns
+                .send_to(
+                    id,
+                    &Outgoing::Err {
+                        reason: util::TOO_LONG_REASON,
+                    },
+                )
+                .await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        }
+        Event::Datagram(from, None) => {
+            debug!("datagram from {from} is over the length limit");
+            metrics.dropped_total.inc("too_long");
+        }
+        Event::Datagram(from, Some(content)) => {
+            metrics.messages_total.fetch_add(1, Ordering::Relaxed);
+            metrics
+                .bytes_total
+                .fetch_add(content.len() as u64, Ordering::Relaxed);
+
+            // only ever sent while there's a socket to receive them on
+            let Some(udp) = udp else {
+                return Ok(());
+            };
+            let sender = udp.sender(from);
+
+            if config
+                .rate_limit
+                .is_some_and(|limit| !sender.bucket.try_take(limit))
+            {
+                debug!("datagram sender {from} is over the rate limit");
+                metrics.dropped_total.inc("rate_limit");
+                return Ok(());
+            }
+
+            let content = sanitize(&content, config);
+            // tcp clients reading lines can only be sent text, one bad datagram would cut them all off
+            if config.framing == Framing::Lines
+                && !config.websocket
+                && std::str::from_utf8(&content).is_err()
+            {
+                debug!("datagram from {from} isn't valid utf-8");
+                metrics.dropped_total.inc("invalid_utf8");
+                return Ok(());
+            }
+            let content = match screen(&content, &mut sender.recent, config) {
+                Ok(content) => content,
+                Err(refused) => {
+                    debug!("not relaying datagram from {from}: {refused:?}");
+                    metrics.dropped_total.inc(refused.label());
+                    return Ok(());
+                }
+            };
+
+            let from_name = from.to_string();
+            let seq = roster.history.next_seq(util::DEFAULT_ROOM);
+            let sent_at = util::unix_millis(SystemTime::now());
+            // datagrams can't be told apart by sender for long enough to number them
+            let msg = Outgoing::Message {
+                seq,
+                from: &from_name,
+                sender_seq: None,
+                sent_at,
+                content: &content,
+            };
+
+            let failed = conns
+                .broadcast(&msg, |to| roster.room(to) == util::DEFAULT_ROOM)
+                .await;
+            metrics.relayed_total.fetch_add(1, Ordering::Relaxed);
+
+            // other udp peers are sent exactly what tcp clients in the plain protocol would read
+            udp.relay(&config.protocol.encode(&msg, &config.prefixes), from);
+
+            if let Some(audit) = audit {
+                audit.record(util::DEFAULT_ROOM, seq, from, sent_at, &content);
+            }
+
+            roster.history.push(
+                util::DEFAULT_ROOM,
+                Recorded {
+                    seq,
+                    from: from_name,
+                    sent_at,
+                    content: Bytes::copy_from_slice(&content),
+                },
+            );
+
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+        }
+        Event::NewMessage(id, msg) => {
+            metrics.messages_total.fetch_add(1, Ordering::Relaxed);
+            metrics
+                .bytes_total
+                .fetch_add(msg.len() as u64, Ordering::Relaxed);
 
-            let framed = FramedStream {
-                inner: framed,
-                port: event.port,
+            let Some(connection) = conns.get_mut(id) else {
+                trace!("dropping message from already disconnected client {id}");
+                return Ok(());
             };
 
-            conns.push(framed);
+            connection.last_activity = Instant::now();
+            connection.bytes_in += msg.len() as u64;
+
+            match config.protocol.decode(msg) {
+                Ok(Incoming::Pong) => connection.missed_pongs = 0,
+                Ok(Incoming::Message(_))
+                    if config
+                        .rate_limit
+                        .is_some_and(|limit| !connection.bucket.try_take(limit)) =>
+                {
+                    debug!("client {id} is over the rate limit");
+                    metrics.dropped_total.inc("rate_limit");
+
+                    let reply = Outgoing::Err {
+                        reason: util::RATE_LIMITED_REASON,
+                    };
+                    let failed = conns.send_to(id, &reply).await;
+                    disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+                }
+                Ok(Incoming::Message(msg)) => {
+                    handle_message(id, &msg, conns, roster, audit, config).await
+                }
+                Err(e) => {
+                    debug!("bad json from {id}: {e}");
+
+                    let reply = Outgoing::Err {
+                        reason: util::BAD_JSON_REASON,
+                    };
+                    let failed = conns.send_to(id, &reply).await;
+                    disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
+                }
+            }
+        }
+        Event::ClientDisconnected(id, reason) => {
+            disconnect(conns, roster, [id], reason).await;
+        }
+        Event::RosterQuery(reply) => {
+            let peers = conns
+                .iter()
+                .map(|(id, connection)| Peer {
+                    id,
+                    nick: roster.nicks.get(&id).cloned(),
+                    tag: roster.tags.get(&id).cloned(),
+                    room: roster.room(id).to_owned(),
+                    addr: connection.addr,
+                })
+                .collect();
+
+            // the caller may have stopped waiting, which is fine
+            let _ = reply.send(peers);
+        }
+        Event::Draining => {}
+        Event::Announcement(content) => {
+            let failed = conns
+                .broadcast(&Outgoing::System { content: &content }, |_| true)
+                .await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
         }
-        EventKind::NewMessage(msg) => {
-            let msg = util::build_message_msg(event.port, &msg);
+        Event::Keepalive => {
+            let Some(keepalive) = config.keepalive else {
+                return Ok(());
+            };
+
+            let ping = config.protocol.encode(&Outgoing::Ping, &config.prefixes);
 
-            for connection in conns.iter_mut() {
-                if connection.port == event.port {
+            let mut unresponsive = Vec::new();
+            let mut failed = Vec::new();
+
+            for (id, connection) in conns.iter_mut() {
+                if connection.missed_pongs >= keepalive.max_missed {
+                    info!("client {id} missed {} pongs", connection.missed_pongs);
+                    unresponsive.push(id);
                     continue;
                 }
 
-                connection.inner.send(&msg).await?;
+                if !connection.send(id, &ping).await {
+                    failed.push(id);
+                    continue;
+                }
 
-                trace!("sent message to {}", connection.port);
+                connection.missed_pongs += 1;
             }
+
+            disconnect(conns, roster, unresponsive, DisconnectReason::Unresponsive).await;
+            disconnect(conns, roster, failed, DisconnectReason::SendFailed).await;
         }
     };
 
+    // every branch that returns early leaves the connections as they were
+    metrics
+        .connections
+        .store(conns.len() as u64, Ordering::Relaxed);
+    metrics
+        .peak_connections
+        .fetch_max(conns.len() as u64, Ordering::Relaxed);
+    metrics
+        .queued_bytes
+        .store(conns.queued_bytes() as u64, Ordering::Relaxed);
+
     Ok(())
 }
 
-#[instrument(level = Level::DEBUG, skip(conns), ret, err(level = Level::ERROR))]
-async fn select_next_event(
-    listener: &TcpListener,
-    conns: &mut SelectAll<FramedStream>,
-) -> Result<Event, std::io::Error> {
-    // select_all will panic if the underlying iterable is empty
-    if conns.is_empty() {
-        debug!("no open connections");
+/// a connection that's ready to join
+#[derive(Debug)]
+struct Accepted {
+    id: ClientId,
+    /// `None` for unix socket peers
+    addr: Option<SocketAddr>,
+    /// authenticated with the admin token
+    admin: bool,
+    reader: Reader,
+    writer: Writer,
+}
 
-        let (sock, addr) = listener.accept().await?;
+/// resolves to the connection once it's ready to join, or `None` if it never will be
+type Pending = BoxFuture<'static, Option<Accepted>>;
 
-        let event = Event {
-            kind: EventKind::NewConnection(sock),
-            port: addr.port(),
-        };
+/// what a client proved it may do while authenticating
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Denied,
+    Client,
+    Admin,
+}
+
+/// hands out ids as clients are accepted, shared with the connections still being established
+#[derive(Debug, Clone)]
+struct Ids {
+    strategy: ClientIds,
+    counter: Arc<AtomicU64>,
+}
+
+impl Ids {
+    /// `token` is what the client authenticated with, if it had to
+    fn assign(&self, addr: Option<SocketAddr>, token: Option<&str>) -> ClientId {
+        let id = ClientId::derive(&self.strategy, addr, token, &self.counter);
+
+        match addr {
+            Some(addr) => info!(client = %id, %addr, "accepted {addr} as client {id}"),
+            None => info!(client = %id, "accepted unix socket peer as client {id}"),
+        }
 
-        return Ok(event);
+        id
     }
+}
+
+/// accepts sockets, running tls handshakes and authentication
+/// alongside the event loop rather than blocking it
+struct Acceptor {
+    listeners: Vec<Listener>,
+    ids: Ids,
+    /// tcp connections start with a PROXY protocol header
+    proxy_protocol: bool,
+    codec: FrameCodec,
+    protocol: Protocol,
+    prefixes: Arc<Prefixes>,
+    tls: Option<TlsAcceptor>,
+    handshake_timeout: Duration,
+    websocket: bool,
+    max_length: usize,
+    auth_token: Option<Arc<str>>,
+    admin_token: Option<Arc<str>>,
+    auth_timeout: Duration,
+    /// `None` unless health probes are answered
+    health_probe_window: Option<Duration>,
+    tcp_nodelay: bool,
+    tcp_keepalive: Option<TcpKeepalive>,
+    access: Arc<RwLock<AccessList>>,
+    /// each a task of its own, so handshakes keep going while the event loop is busy elsewhere,
+    /// e.g. waiting on a slow client, and are aborted along with the acceptor
+    pending: JoinSet<Option<Accepted>>,
+    max_pending: Option<usize>,
+    /// how long the last pause was, zero while accepting works
+    backoff: Duration,
+    /// accepting is paused until then after running out of resources
+    retry_at: Option<Instant>,
+}
+
+impl Acceptor {
+    /// resolves once a connection is ready to be used,
+    /// only errors if a listener is broken for good
+    async fn accept(&mut self) -> Result<Accepted, std::io::Error> {
+        loop {
+            // at the limit the listeners aren't polled at all,
+            // leaving the kernel to hold on to new connections until a slot frees up
+            let has_room = self.max_pending.is_none_or(|max| self.pending.len() < max);
+            let backing_off = self.retry_at.is_some();
+
+            select! {
+                res = transport::accept_any(&self.listeners), if has_room && !backing_off => {
+                    let (sock, addr) = match res {
+                        Ok(accepted) => {
+                            self.backoff = Duration::ZERO;
+                            accepted
+                        }
+                        Err(e) => match AcceptFailure::of(&e) {
+                            AcceptFailure::Connection => {
+                                debug!("connection lost while accepting it: {e}");
+                                continue;
+                            }
+                            AcceptFailure::Exhausted => {
+                                self.backoff = transport::accept_backoff(self.backoff);
+                                warn!("accepting failed, trying again in {:?}: {e}", self.backoff);
+                                self.retry_at = Some(Instant::now() + self.backoff);
+                                continue;
+                            }
+                            AcceptFailure::Fatal => return Err(e),
+                        },
+                    };
+
+                    if let Socket::Tcp(sock) = &sock {
+                        // the connection works all the same, only slower to send or to notice dead peers
+                        if let Err(e) = transport::configure(sock, self.tcp_nodelay, self.tcp_keepalive) {
+                            debug!("failed to set socket options: {e}");
+                        }
+                    }
+
+                    // unix socket access is down to file permissions instead,
+                    // behind a balancer both it and the client it speaks for must be let in
+                    if let Some(addr) = addr.filter(|addr| !self.permits(addr.ip())) {
+                        // dropping the socket closes it
+                        info!("refusing connection from {addr}");
+                        continue;
+                    }
 
-    let event = select! {
-        Ok((sock, addr)) = listener.accept() => {
-            Event {
-                kind: EventKind::NewConnection(sock),
-                port: addr.port(),
+                    if self.tls.is_none()
+                        && !self.websocket
+                        && self.auth_token.is_none()
+                        && self.health_probe_window.is_none()
+                        && !self.proxy_protocol
+                    {
+                        let id = self.ids.assign(addr, None);
+                        let (reader, writer) = framed::with_codec(sock, &self.codec);
+                        return Ok(Accepted {
+                            id,
+                            addr,
+                            admin: false,
+                            reader,
+                            writer,
+                        });
+                    }
+
+                    self.pending.spawn(self.establish(addr, sock));
+                }
+
+                Some(res) = self.pending.join_next() => match res {
+                    Ok(Some(conn)) => return Ok(conn),
+                    Ok(None) => {}
+                    Err(e) => error!("establishing a connection failed: {e}"),
+                },
+
+                _ = maybe_sleep_until(self.retry_at), if backing_off => {
+                    self.retry_at = None;
+                }
             }
         }
+    }
+
+    fn permits(&self, ip: IpAddr) -> bool {
+        permitted(&self.access, ip)
+    }
+
+    fn establish(&self, addr: Option<SocketAddr>, sock: Socket) -> Pending {
+        let ids = self.ids.clone();
+        let proxy_protocol = self.proxy_protocol;
+        let access = Arc::clone(&self.access);
+        let codec = self.codec.clone();
+        let protocol = self.protocol;
+        let prefixes = Arc::clone(&self.prefixes);
+        let tls = self.tls.clone();
+        let handshake_timeout = self.handshake_timeout;
+        let websocket = self.websocket;
+        let max_length = self.max_length;
+        let auth_token = self.auth_token.clone();
+        let admin_token = self.admin_token.clone();
+        let auth_timeout = self.auth_timeout;
+        let health_probe_window = self.health_probe_window;
+
+        Box::pin(async move {
+            let (addr, sock) = match (proxy_protocol, addr, sock) {
+                (true, Some(balancer), Socket::Tcp(mut sock)) => {
+                    let header = transport::read_proxy_header(&mut sock);
+
+                    match tokio::time::timeout(handshake_timeout, header).await {
+                        Ok(Ok(client)) => (client.or(addr), Socket::Tcp(sock)),
+                        Ok(Err(e)) => {
+                            debug!("bad proxy header from {balancer}: {e}");
+                            return None;
+                        }
+                        Err(_) => {
+                            debug!("proxy header from {balancer} timed out");
+                            return None;
+                        }
+                    }
+                }
+                (_, addr, sock) => (addr, sock),
+            };
+
+            if let Some(addr) = addr.filter(|addr| !permitted(&access, addr.ip())) {
+                info!("refusing connection from {addr}");
+                return None;
+            }
+
+            // no id until the client has authenticated, a custom one may depend on the token
+            let peer = addr.map_or_else(|| "unix socket peer".to_owned(), |addr| addr.to_string());
 
-        Some(res) = conns.next() => {
-            if let Ok((port, msg)) = res {
-                Event {
-                    kind: EventKind::NewMessage(msg),
-                    port,
+            let sock = match (health_probe_window, sock) {
+                (Some(window), Socket::Tcp(mut sock)) => {
+                    match transport::answer_health_probe(&mut sock, window).await {
+                        Ok(false) => Socket::Tcp(sock),
+                        Ok(true) => {
+                            debug!("answered health probe from {peer}");
+                            return None;
+                        }
+                        Err(e) => {
+                            debug!("error reading from {peer}: {e}");
+                            return None;
+                        }
+                    }
+                }
+                (_, sock) => sock,
+            };
+
+            let sock = match (tls, sock) {
+                (Some(tls), Socket::Tcp(sock)) => {
+                    match tokio::time::timeout(handshake_timeout, tls.accept(sock)).await {
+                        Ok(Ok(sock)) => Socket::Tls(Box::new(sock)),
+                        Ok(Err(e)) => {
+                            debug!("tls handshake with {peer} failed: {e}");
+                            return None;
+                        }
+                        Err(_) => {
+                            debug!("tls handshake with {peer} timed out");
+                            return None;
+                        }
+                    }
+                }
+                (_, sock) => sock,
+            };
+
+            let (mut reader, mut writer) = if websocket {
+                let upgrade = framed::with_websocket(sock, max_length);
+
+                match tokio::time::timeout(handshake_timeout, upgrade).await {
+                    Ok(Ok(halves)) => halves,
+                    Ok(Err(e)) => {
+                        debug!("websocket upgrade for {peer} failed: {e}");
+                        return None;
+                    }
+                    Err(_) => {
+                        debug!("websocket upgrade for {peer} timed out");
+                        return None;
+                    }
                 }
             } else {
-                // connection closed
-                // pretty sure this branch will never be reached
-                // leaving a panic in case i'm wrong
-                unreachable!()
+                framed::with_codec(sock, &codec)
+            };
+
+            let access = match &auth_token {
+                Some(token) => tokio::time::timeout(
+                    auth_timeout,
+                    authenticate(&peer, &mut reader, token, admin_token.as_deref(), protocol),
+                )
+                .await
+                .unwrap_or_else(|_| {
+                    debug!("{peer} didn't authenticate within {auth_timeout:?}");
+                    Access::Denied
+                }),
+                None => Access::Client,
+            };
+
+            let token = match access {
+                Access::Admin => admin_token.as_deref(),
+                Access::Client => auth_token.as_deref(),
+                Access::Denied => {
+                    info!("rejecting {peer}, unauthorized");
+
+                    let reply = Outgoing::Err {
+                        reason: util::UNAUTHORIZED_REASON,
+                    };
+                    // dropping the writer afterwards closes the socket
+                    let msg = protocol.encode(&reply, &prefixes);
+                    let _ = writer.send(OutFrame::Plain(msg)).await;
+
+                    return None;
+                }
+            };
+
+            let id = ids.assign(addr, token);
+            let admin = access == Access::Admin;
+            if admin {
+                info!("client {id} authenticated as admin");
             }
 
+            Some(Accepted {
+                id,
+                addr,
+                admin,
+                reader,
+                writer,
+            })
+        })
+    }
+}
+
+/// waits for the client's first frame and checks it's `AUTH <token>`,
+/// or `AUTH <admin token>`
+async fn authenticate(
+    peer: &str,
+    reader: &mut Reader,
+    token: &str,
+    admin_token: Option<&str>,
+    protocol: Protocol,
+) -> Access {
+    let frame = match reader.next().await {
+        Some(Ok(frame)) => frame,
+        Some(Err(e)) => {
+            debug!("error reading from {peer}: {e}");
+            return Access::Denied;
         }
+        None => return Access::Denied,
     };
 
-    Ok(event)
+    let Ok(Incoming::Message(msg)) = protocol.decode(frame) else {
+        return Access::Denied;
+    };
+
+    let Some(given) = msg.strip_prefix(b"AUTH ") else {
+        return Access::Denied;
+    };
+
+    if admin_token.is_some_and(|admin_token| util::constant_time_eq(given, admin_token.as_bytes()))
+    {
+        Access::Admin
+    } else if util::constant_time_eq(given, token.as_bytes()) {
+        Access::Client
+    } else {
+        Access::Denied
+    }
+}
+
+/// ticks the interval if there is one, otherwise never resolves
+async fn maybe_tick(interval: &mut Option<Interval>) -> Instant {
+    match interval {
+        Some(interval) => interval.tick().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// receives from the socket if there is one, otherwise never resolves
+async fn maybe_recv(udp: Option<&mut Udp>) -> std::io::Result<(SocketAddr, Option<Bytes>)> {
+    match udp {
+        Some(udp) => udp.recv().await,
+        None => std::future::pending().await,
+    }
 }
 
-#[instrument(level = Level::DEBUG, skip_all, ret, err(level = Level::ERROR))]
-pub async fn serve<A: ToSocketAddrs>(bind: A) -> Result<(), std::io::Error> {
-    let mut conns = SelectAll::new();
+/// waits for the signal if it's handled, otherwise never resolves
+async fn maybe_signal(signal: &mut Option<Signal>) -> Option<()> {
+    match signal {
+        Some(signal) => signal.recv().await,
+        None => std::future::pending().await,
+    }
+}
 
-    let listener = TcpListener::bind(bind).await?;
+/// sleeps until the deadline if there is one, otherwise never resolves
+async fn maybe_sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
 
-    info!("started listening on {}", listener.local_addr()?);
+#[instrument(level = Level::DEBUG, skip(acceptor, conns, idle_check, keepalive_check, inbox, udp), ret, err(level = Level::ERROR))]
+async fn select_next_event(
+    acceptor: &mut Acceptor,
+    conns: &mut Connections,
+    idle_check: &mut Interval,
+    idle_timeout: Duration,
+    keepalive_check: &mut Option<Interval>,
+    inbox: &mut Inbox,
+    mut udp: Option<&mut Udp>,
+) -> Result<Event, std::io::Error> {
+    let draining = inbox.draining();
 
-    let event_loop = async {
-        loop {
-            if let Ok(event) = select_next_event(&listener, &mut conns).await {
-                let _ = handle_event(event, &mut conns).await;
+    let event = loop {
+        select! {
+            // while draining new connections are left to the listen backlog until shutdown
+            res = acceptor.accept(), if !draining => {
+                break Event::NewConnection(Box::new(res?));
+            }
+
+            // an empty SelectAll yields `None` right away, which just disables this arm for the round
+            Some((id, ip, res)) = conns.readers.next() => {
+                match res {
+                    // the codec only knows the ceiling, not what each client settled on
+                    Some(Ok(msg)) if conns
+                        .get(id)
+                        .is_some_and(|conn| msg.len() > conn.max_line_length) =>
+                    {
+                        break Event::MessageTooLong(id);
+                    }
+                    Some(Ok(msg)) => break Event::NewMessage(id, msg),
+                    Some(Err(FrameError::TooLong)) => break Event::MessageTooLong(id),
+                    Some(Err(e)) => {
+                        debug!(client = %id, ip = ?ip, "error reading from {id}: {e}");
+
+                        break Event::ClientDisconnected(id, DisconnectReason::ReadError);
+                    }
+                    // a writer that gave up ends the reader too, otherwise the client hung up
+                    None if conns.get(id).is_some_and(|conn| conn.writer_stopped()) => {
+                        break Event::ClientDisconnected(id, DisconnectReason::SendFailed);
+                    }
+                    None => break Event::ClientDisconnected(id, DisconnectReason::Closed),
+                }
+            }
+
+            _ = idle_check.tick() => {
+                let Some((id, _)) = conns
+                    .iter()
+                    .find(|(_, conn)| conn.last_activity.elapsed() > idle_timeout)
+                else {
+                    continue;
+                };
+
+                info!("client {id} idle for over {idle_timeout:?}");
+
+                // there may be more idle connections,
+                // check again right away instead of waiting out the period
+                idle_check.reset_immediately();
+
+                break Event::ClientDisconnected(id, DisconnectReason::Idle);
+            }
+
+            _ = maybe_tick(keepalive_check) => {
+                break Event::Keepalive;
+            }
+
+            res = maybe_recv(udp.as_deref_mut()) => match res {
+                // held to the same lists as connections, a denied sender isn't even a peer
+                Ok((from, _)) if !permitted(&inbox.access, from.ip()) => {
+                    debug!("dropping datagram from {from}, it isn't allowed");
+                    conns.metrics.dropped_total.inc("denied");
+                }
+                Ok((from, content)) => break Event::Datagram(from, content),
+                // e.g. a peer relayed to earlier turned out to be unreachable, the socket itself is fine
+                Err(e) => debug!("error receiving datagram: {e}"),
+            },
+
+            // disabled for this round once every sender is dropped
+            Some(msg) = inbox.announcements.recv() => {
+                break Event::Announcement(msg);
+            }
+
+            // likewise once every handle is dropped
+            Some(reply) = inbox.roster_queries.recv() => {
+                break Event::RosterQuery(reply);
+            }
+
+            Some(()) = maybe_signal(&mut inbox.terminate), if !draining => {
+                info!(
+                    "draining {} clients for up to {:?}",
+                    conns.len(),
+                    inbox.drain_timeout
+                );
+                inbox.drain_deadline = Some(Instant::now() + inbox.drain_timeout);
+
+                break Event::Draining;
+            }
+
+            _ = maybe_sleep_until(inbox.drain_deadline) => {
+                break Event::Draining;
+            }
+
+            Some(()) = maybe_signal(&mut inbox.hangup) => {
+                inbox.reload();
             }
         }
     };
 
-    let shutdown = async {
-        tokio::signal::ctrl_c()
-            .await
-            .expect("signal registration to work");
-    };
+    Ok(event)
+}
+
+/// a bound server that hasn't started accepting connections yet
+///
+/// binding separately from running lets callers learn the address before any client connects,
+/// which matters when binding port 0
+pub struct Server {
+    config: ServerConfig,
+    acceptor: Acceptor,
+    metrics_listener: Option<TcpListener>,
+    udp: Option<Udp>,
+    log_file: Option<File>,
+    roster: RosterHandle,
+    roster_queries: mpsc::Receiver<oneshot::Sender<Vec<Peer>>>,
+    events: broadcast::Sender<ServerEvent>,
+}
+
+impl Server {
+    /// validates `config` and binds every listener it asks for
+    pub async fn bind(config: ServerConfig) -> Result<Self, ServeError> {
+        if config.framing == Framing::Delimited && config.delimiters.is_empty() {
+            return Err(ServeError::InvalidConfig(
+                "delimited framing needs at least one delimiter",
+            ));
+        }
+
+        if config.tls.is_some() && config.unix_socket.is_some() {
+            return Err(ServeError::InvalidConfig(
+                "tls isn't supported on unix sockets",
+            ));
+        }
+
+        if config.admin_token.is_some() && config.auth_token.is_none() {
+            return Err(ServeError::InvalidConfig("an admin token needs an auth token, admins identify themselves while authenticating"));
+        }
+
+        if config.max_handshakes == Some(0) {
+            return Err(ServeError::InvalidConfig(
+                "at least one handshake must be allowed at a time",
+            ));
+        }
+
+        if config.idle_timeout.is_zero() {
+            return Err(ServeError::InvalidConfig(
+                "the idle timeout must be longer than zero",
+            ));
+        }
+
+        if config
+            .keepalive
+            .is_some_and(|keepalive| keepalive.interval.is_zero())
+        {
+            return Err(ServeError::InvalidConfig(
+                "the keepalive interval must be longer than zero",
+            ));
+        }
+
+        if config.outbound_queue_len == 0 {
+            return Err(ServeError::InvalidConfig(
+                "outbound queues must have room for at least one message",
+            ));
+        }
+
+        if let Some(limit) = config.rate_limit {
+            // NaN or infinity never limit anything, zero never lets anything through
+            if !(limit.per_second.is_finite() && limit.per_second > 0.0) {
+                return Err(ServeError::InvalidConfig(
+                    "the rate limit must be a positive, finite number per second",
+                ));
+            }
+
+            if limit.burst == 0 {
+                return Err(ServeError::InvalidConfig(
+                    "the rate limit must allow a burst of at least one message",
+                ));
+            }
+        }
+
+        let tls = config.tls.as_ref().map(tls::load_acceptor).transpose()?;
+        let listeners = match &config.unix_socket {
+            Some(path) => vec![Listener::Unix {
+                inner: UnixListener::bind(path).map_err(|source| ServeError::BindUnix {
+                    path: path.clone(),
+                    source,
+                })?,
+                path: path.clone(),
+            }],
+            None if config.bind.is_empty() => {
+                return Err(ServeError::InvalidConfig("no addresses to listen on"));
+            }
+            None => {
+                let mut listeners = Vec::with_capacity(config.bind.len());
+
+                for &addr in &config.bind {
+                    let listener = if config.dual_stack {
+                        transport::bind_dual_stack(addr)
+                    } else {
+                        TcpListener::bind(addr).await
+                    };
+                    let listener = listener.map_err(|source| ServeError::Bind { addr, source })?;
+
+                    listeners.push(Listener::Tcp(listener));
+                }
+
+                listeners
+            }
+        };
 
-    select! {
-        _ = event_loop => {},
-        _ = shutdown => {
-            info!("shutting down");
-        },
+        let metrics_listener = match config.metrics_addr {
+            Some(addr) => Some(
+                TcpListener::bind(addr)
+                    .await
+                    .map_err(|source| ServeError::Bind { addr, source })?,
+            ),
+            None => None,
+        };
+
+        let udp = match config.udp_bind {
+            Some(addr) => {
+                let socket = UdpSocket::bind(addr)
+                    .await
+                    .map_err(|source| ServeError::Bind { addr, source })?;
+                Some(Udp::new(
+                    socket,
+                    config.max_line_length,
+                    config.udp_peer_ttl,
+                ))
+            }
+            None => None,
+        };
+
+        let log_file = match &config.log_file {
+            Some(path) => Some(AuditLog::open(path).await?),
+            None => None,
+        };
+
+        let acceptor = Acceptor {
+            listeners,
+            ids: Ids {
+                strategy: config.client_ids.clone(),
+                counter: Arc::new(AtomicU64::new(0)),
+            },
+            proxy_protocol: config.proxy_protocol,
+            codec: FrameCodec::new(config.framing, &config.delimiters, config.max_line_length),
+            protocol: config.protocol,
+            prefixes: Arc::new(config.prefixes.clone()),
+            tls,
+            // a handshake that stalls is no different from a client gone silent
+            handshake_timeout: config.idle_timeout,
+            websocket: config.websocket,
+            max_length: config.max_line_length,
+            auth_token: config.auth_token.as_deref().map(Arc::from),
+            admin_token: config.admin_token.as_deref().map(Arc::from),
+            auth_timeout: config.auth_timeout,
+            health_probe_window: config.health_probe_window,
+            tcp_nodelay: config.tcp_nodelay,
+            tcp_keepalive: config.tcp_keepalive,
+            access: Arc::new(RwLock::new(AccessList {
+                allow: config.allow.clone(),
+                deny: config.deny.clone(),
+            })),
+            pending: JoinSet::new(),
+            max_pending: config.max_handshakes,
+            backoff: Duration::ZERO,
+            retry_at: None,
+        };
+
+        // queries are tiny and answered quickly, a few may as well wait in line
+        let (queries, roster_queries) = mpsc::channel(16);
+
+        Ok(Server {
+            config,
+            acceptor,
+            metrics_listener,
+            udp,
+            log_file,
+            roster: RosterHandle { queries },
+            roster_queries,
+            events: broadcast::channel(EVENT_BUFFER).0,
+        })
     }
 
-    Ok(())
+    /// the first address clients can connect to, `None` when listening on a unix socket
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addrs().into_iter().next()
+    }
+
+    /// every address clients can connect to, in the order they were configured
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.acceptor
+            .listeners
+            .iter()
+            .filter_map(|listener| match listener {
+                Listener::Tcp(listener) => listener.local_addr().ok(),
+                Listener::Unix { .. } => None,
+            })
+            .collect()
+    }
+
+    /// where datagrams are taken, `None` unless configured
+    pub fn udp_addr(&self) -> Option<SocketAddr> {
+        self.udp.as_ref().and_then(|udp| udp.local_addr().ok())
+    }
+
+    /// where metrics are served, `None` unless configured,
+    /// the actual port when the configured one was 0
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_listener
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok())
+    }
+
+    /// lets the host application see who's connected once the server is running,
+    /// callers of [`serve`] that need this should bind and run separately instead
+    pub fn roster(&self) -> RosterHandle {
+        self.roster.clone()
+    }
+
+    /// every join, leave and broadcast message from the moment this is called,
+    /// ends once the server has stopped
+    ///
+    /// the server never waits on subscribers, one that falls too far behind
+    /// is told how much it missed with [`ServerEvent::Missed`] instead
+    pub fn events(&self) -> impl Stream<Item = ServerEvent> + Send + 'static {
+        futures::stream::unfold(self.events.subscribe(), |mut events| async move {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(missed)) => ServerEvent::Missed(missed),
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+
+            Some((event, events))
+        })
+    }
+
+    /// runs until either ctrl-c is received or `shutdown` resolves
+    ///
+    /// with `drain_on_sigterm` SIGTERM drains instead, new connections are no longer accepted
+    /// but everyone connected is served until they leave or `drain_timeout` passes, whichever comes first
+    ///
+    /// every line sent on `announcements` is broadcast to all clients as `SYSTEM:<line>`,
+    /// the sender may be dropped if the host application has nothing to say
+    ///
+    /// the stats are only returned if the server stopped because it was asked to
+    #[instrument(level = Level::DEBUG, skip_all, ret, err(level = Level::ERROR))]
+    pub async fn run(
+        self,
+        announcements: mpsc::Receiver<String>,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<ServeStats, std::io::Error> {
+        let started = Instant::now();
+        let Server {
+            config,
+            mut acceptor,
+            metrics_listener,
+            mut udp,
+            log_file,
+            // only handles given out keep the query channel open
+            roster: _,
+            roster_queries,
+            events,
+        } = self;
+
+        let metrics = Arc::new(Metrics::default());
+        let mut conns = Connections::new(&config, Arc::clone(&metrics));
+        let mut roster = Roster::new(History::new(config.history_len, config.history_ttl), events);
+        let audit = log_file.map(AuditLog::spawn);
+        let mut idle_check =
+            tokio::time::interval(config.idle_timeout.min(util::MAX_IDLE_CHECK_PERIOD));
+        let mut keepalive_check = config
+            .keepalive
+            .map(|keepalive| tokio::time::interval(keepalive.interval));
+
+        for listener in &acceptor.listeners {
+            info!("started listening on {listener}");
+        }
+
+        let mut inbox = Inbox {
+            announcements,
+            roster_queries,
+            terminate: config
+                .drain_on_sigterm
+                .then(|| signal(SignalKind::terminate()))
+                .transpose()?,
+            hangup: config
+                .reload_on_sighup
+                .then(|| signal(SignalKind::hangup()))
+                .transpose()?,
+            config_file: config.config_file.clone(),
+            access: Arc::clone(&acceptor.access),
+            drain_timeout: config.drain_timeout,
+            drain_deadline: None,
+        };
+
+        let event_loop = async {
+            loop {
+                if let Some(deadline) = inbox.drain_deadline {
+                    if conns.is_empty() {
+                        info!("drained every client");
+                        break Ok(());
+                    }
+
+                    if Instant::now() >= deadline {
+                        info!("drain timed out with {} clients left", conns.len());
+                        break Ok(());
+                    }
+                }
+
+                // only a broken listener gets this far, there's no serving anyone new after that
+                let event = match select_next_event(
+                    &mut acceptor,
+                    &mut conns,
+                    &mut idle_check,
+                    config.idle_timeout,
+                    &mut keepalive_check,
+                    &mut inbox,
+                    udp.as_mut(),
+                )
+                .await
+                {
+                    Ok(event) => event,
+                    Err(e) => break Err(e),
+                };
+
+                let _ = handle_event(
+                    event,
+                    &mut conns,
+                    &mut roster,
+                    audit.as_ref(),
+                    &config,
+                    &metrics,
+                    udp.as_mut(),
+                )
+                .await;
+
+                // give the writer tasks a chance to drain their queues,
+                // otherwise a burst of messages can fill them up before they ever run
+                tokio::task::yield_now().await;
+            }
+        };
+
+        let ctrl_c = async {
+            tokio::signal::ctrl_c()
+                .await
+                .expect("signal registration to work");
+        };
+
+        let metrics_endpoint = async {
+            match metrics_listener {
+                Some(listener) => metrics::serve(listener, Arc::clone(&metrics)).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let throughput_log = async {
+            if config.log_throughput {
+                metrics::log_throughput(&metrics).await
+            } else {
+                std::future::pending().await
+            }
+        };
+
+        let stopped = select! {
+            res = event_loop => res,
+            res = metrics_endpoint => res,
+            _ = throughput_log => Ok(()),
+            _ = ctrl_c => {
+                info!("shutting down");
+                Ok(())
+            },
+            _ = shutdown => {
+                info!("shutdown requested");
+                Ok(())
+            },
+        };
+
+        // clients still get their goodbye, it's only new ones that can't be served
+        if let Err(e) = &stopped {
+            error!("accepting connections failed for good, shutting down: {e}");
+        }
+
+        let bye = Outgoing::Bye {
+            reason: util::SHUTDOWN_REASON,
+        };
+        // saying goodbye and flushing share the one deadline,
+        // a blocking backpressure policy mustn't keep the server from stopping either
+        let deadline = Instant::now() + config.shutdown_timeout;
+        let total = conns.len();
+
+        // everyone is about to be disconnected anyway, failures don't matter here
+        let _ = tokio::time::timeout_at(deadline, conns.broadcast(&bye, |_| true)).await;
+        let closed = conns.close(deadline).await;
+
+        info!(
+            "flushed {} of {total} clients, {} went away and {} were cut off after {:?}",
+            closed.flushed, closed.failed, closed.cut_off, config.shutdown_timeout
+        );
+
+        if let Some(audit) = audit {
+            audit.close().await;
+        }
+
+        let stats = ServeStats::new(&metrics, started.elapsed());
+        info!("{stats}");
+
+        stopped.map(|()| stats)
+    }
+}
+
+/// binds and runs the broadcast server in one go, see [`Server::run`]
+pub async fn serve(
+    config: ServerConfig,
+    announcements: mpsc::Receiver<String>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<ServeStats, ServeError> {
+    let stats = Server::bind(config)
+        .await?
+        .run(announcements, shutdown)
+        .await?;
+
+    Ok(stats)
 }
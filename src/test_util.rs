@@ -0,0 +1,121 @@
+//! helpers for exercising a running server, only built with the `test-util` feature
+
+use std::{
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use futures::{future, SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_util::codec::{Framed, LinesCodec, LinesCodecError};
+
+use crate::server::{ClientId, Message};
+
+/// a plain protocol client speaking newline delimited lines
+#[derive(Debug)]
+pub struct TestClient {
+    framed: Framed<TcpStream, LinesCodec>,
+    id: ClientId,
+}
+
+impl TestClient {
+    /// connects to `addr` and waits for the `LOGIN:<id> v<version>` greeting
+    pub async fn connect(addr: SocketAddr) -> io::Result<Self> {
+        Self::from_stream(TcpStream::connect(addr).await?).await
+    }
+
+    /// like [`TestClient::connect`] but over an already connected socket,
+    /// for tests that need control over how it was set up
+    pub async fn from_stream(stream: TcpStream) -> io::Result<Self> {
+        let mut framed = Framed::new(stream, LinesCodec::new());
+
+        let login = framed
+            .next()
+            .await
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?
+            .map_err(into_io)?;
+        let Ok(Message::Login { id, .. }) = login.parse() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected login, got {login}"),
+            ));
+        };
+
+        Ok(TestClient { framed, id })
+    }
+
+    /// the id the server assigned to this client
+    pub fn id(&self) -> ClientId {
+        self.id
+    }
+
+    pub async fn send_line(&mut self, line: &str) -> io::Result<()> {
+        self.framed.send(line).await.map_err(into_io)
+    }
+
+    /// `None` once the server has closed the connection
+    pub async fn recv_line(&mut self) -> io::Result<Option<String>> {
+        self.framed.next().await.transpose().map_err(into_io)
+    }
+
+    /// like [`TestClient::recv_line`] but parsed, lines that don't parse are an error
+    pub async fn recv_message(&mut self) -> io::Result<Option<Message>> {
+        let Some(line) = self.recv_line().await? else {
+            return Ok(None);
+        };
+
+        line.parse()
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// how long `line` takes to come back, only useful against a server in loopback mode
+    ///
+    /// anything else received in the meantime is skipped
+    pub async fn round_trip(&mut self, line: &str) -> io::Result<Duration> {
+        let started = Instant::now();
+        self.send_line(line).await?;
+
+        loop {
+            let received = self
+                .recv_line()
+                .await?
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+
+            if received.ends_with(line) {
+                return Ok(started.elapsed());
+            }
+        }
+    }
+}
+
+/// connects `clients` clients to a server in loopback mode and has each of them
+/// round trip `line` `messages` times, returning every round trip's duration
+pub async fn generate_load(
+    addr: SocketAddr,
+    clients: usize,
+    messages: usize,
+    line: &str,
+) -> io::Result<Vec<Duration>> {
+    let runs = (0..clients).map(|_| async move {
+        let mut client = TestClient::connect(addr).await?;
+        let mut durations = Vec::with_capacity(messages);
+
+        for _ in 0..messages {
+            durations.push(client.round_trip(line).await?);
+        }
+
+        io::Result::Ok(durations)
+    });
+
+    let durations = future::try_join_all(runs).await?;
+    Ok(durations.into_iter().flatten().collect())
+}
+
+fn into_io(e: LinesCodecError) -> io::Error {
+    match e {
+        LinesCodecError::Io(e) => e,
+        LinesCodecError::MaxLineLengthExceeded => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
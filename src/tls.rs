@@ -0,0 +1,29 @@
+use std::{io, sync::Arc};
+
+use tokio_rustls::{
+    rustls::{
+        self,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    },
+    TlsAcceptor,
+};
+
+use crate::config::Tls;
+
+/// reads the pem encoded certificate chain and private key
+pub(crate) fn load_acceptor(tls: &Tls) -> io::Result<TlsAcceptor> {
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidInput, e);
+
+    let certs = CertificateDer::pem_file_iter(&tls.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(format!("bad certificate {}: {e}", tls.cert.display())))?;
+    let key = PrivateKeyDer::from_pem_file(&tls.key)
+        .map_err(|e| invalid(format!("bad private key {}: {e}", tls.key.display())))?;
+
+    let config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| invalid(e.to_string()))?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -0,0 +1,301 @@
+use std::{
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
+    time::Instant,
+};
+use tokio_rustls::server::TlsStream;
+
+use crate::config::TcpKeepalive;
+
+/// either kind of socket the server can be reached on
+#[derive(Debug)]
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    Unix {
+        inner: UnixListener,
+        /// kept around to clean the socket file up afterwards
+        path: PathBuf,
+    },
+}
+
+impl Listener {
+    /// unix peers have no address worth speaking of so they get `None`
+    pub(crate) async fn accept(&self) -> io::Result<(Socket, Option<SocketAddr>)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (sock, addr) = listener.accept().await?;
+                Ok((Socket::Tcp(sock), Some(addr)))
+            }
+            Listener::Unix { inner, .. } => {
+                let (sock, _) = inner.accept().await?;
+                Ok((Socket::Unix(sock), None))
+            }
+        }
+    }
+}
+
+/// accepts from whichever listener has a connection ready first
+///
+/// `listeners` must not be empty
+pub(crate) async fn accept_any(listeners: &[Listener]) -> io::Result<(Socket, Option<SocketAddr>)> {
+    let accepts = listeners.iter().map(|listener| Box::pin(listener.accept()));
+    let (res, _, _) = futures::future::select_all(accepts).await;
+
+    res
+}
+
+/// how long accepting pauses the first time the server runs out of file descriptors,
+/// doubling every time in a row up to the max
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// how long to pause after [`AcceptFailure::Exhausted`], given the last pause or zero
+pub(crate) fn accept_backoff(last: Duration) -> Duration {
+    (last * 2).clamp(MIN_ACCEPT_BACKOFF, MAX_ACCEPT_BACKOFF)
+}
+
+/// how an accept failed, which decides whether to carry on
+pub(crate) enum AcceptFailure {
+    /// only the connection being accepted was lost, the next accept is unaffected
+    Connection,
+    /// out of file descriptors or memory, accepting again right away would fail the same way
+    Exhausted,
+    /// the listener itself is broken
+    Fatal,
+}
+
+impl AcceptFailure {
+    pub(crate) fn of(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted => return AcceptFailure::Connection,
+            _ => {}
+        }
+
+        match e.raw_os_error() {
+            Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM) => {
+                AcceptFailure::Exhausted
+            }
+            _ => AcceptFailure::Fatal,
+        }
+    }
+}
+
+/// the lines a load balancer may send to check the server is up
+const HEALTH_PROBES: [&[u8]; 2] = [b"HEALTH\n", b"HEALTH\r\n"];
+
+/// how often to look again while only part of what might be a probe has arrived
+const HEALTH_PROBE_RECHECK: Duration = Duration::from_millis(1);
+
+/// answers `OK` and returns true if the first thing `sock` sends within `window` is a `HEALTH` line
+///
+/// only peeks otherwise, so whatever a real client sent is still there for the codec
+pub(crate) async fn answer_health_probe(
+    sock: &mut TcpStream,
+    window: Duration,
+) -> io::Result<bool> {
+    let deadline = Instant::now() + window;
+    let mut buf = [0; 8];
+
+    let probe = loop {
+        let Ok(peeked) = tokio::time::timeout_at(deadline, sock.peek(&mut buf)).await else {
+            return Ok(false);
+        };
+        let peeked = &buf[..peeked?];
+
+        if let Some(probe) = HEALTH_PROBES.iter().find(|probe| peeked.starts_with(probe)) {
+            break probe;
+        }
+
+        if peeked.is_empty() || !HEALTH_PROBES.iter().any(|probe| probe.starts_with(peeked)) {
+            return Ok(false);
+        }
+
+        // peeking returns straight away while there's anything to see, so don't spin on it
+        tokio::time::sleep(HEALTH_PROBE_RECHECK).await;
+    };
+
+    // closing with the probe still unread would reset the connection before `OK` arrives
+    sock.read_exact(&mut buf[..probe.len()]).await?;
+    sock.write_all(b"OK\n").await?;
+    sock.shutdown().await?;
+
+    Ok(true)
+}
+
+/// longest a PROXY protocol v1 header may be, `\r\n` included
+const PROXY_HEADER_MAX_LEN: usize = 107;
+
+/// reads the PROXY protocol v1 header a load balancer sends ahead of anything else,
+/// returns the client's address, `None` if the balancer didn't know it (`PROXY UNKNOWN`)
+pub(crate) async fn read_proxy_header(sock: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    let mut header = Vec::with_capacity(PROXY_HEADER_MAX_LEN);
+
+    // a byte at a time, whatever follows the header belongs to tls or the codec
+    while header.len() < PROXY_HEADER_MAX_LEN && !header.ends_with(b"\n") {
+        header.push(sock.read_u8().await?);
+    }
+
+    parse_proxy_header(&header)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed proxy header"))
+}
+
+/// `PROXY TCP4 <src> <dst> <src port> <dst port>\r\n`, or `TCP6` with ipv6 addresses
+fn parse_proxy_header(header: &[u8]) -> Option<Option<SocketAddr>> {
+    let header = std::str::from_utf8(header.strip_suffix(b"\r\n")?).ok()?;
+    let rest = header.strip_prefix("PROXY ")?;
+
+    // anything may follow, the balancer is only saying it's not passing on a client's address
+    if rest == "UNKNOWN" || rest.starts_with("UNKNOWN ") {
+        return Some(None);
+    }
+
+    let [family, src, dst, src_port, dst_port] = rest.split(' ').collect::<Vec<_>>()[..] else {
+        return None;
+    };
+
+    // the destination is only checked, it's where the balancer was reached rather than this server
+    let src: IpAddr = match family {
+        "TCP4" => {
+            dst.parse::<Ipv4Addr>().ok()?;
+            src.parse::<Ipv4Addr>().ok()?.into()
+        }
+        "TCP6" => {
+            dst.parse::<Ipv6Addr>().ok()?;
+            src.parse::<Ipv6Addr>().ok()?.into()
+        }
+        _ => return None,
+    };
+    dst_port.parse::<u16>().ok()?;
+
+    Some(Some(SocketAddr::new(src, src_port.parse().ok()?)))
+}
+
+/// applies the configured socket options to a freshly accepted tcp connection
+pub(crate) fn configure(
+    sock: &TcpStream,
+    nodelay: bool,
+    keepalive: Option<TcpKeepalive>,
+) -> io::Result<()> {
+    sock.set_nodelay(nodelay)?;
+
+    if let Some(keepalive) = keepalive {
+        let params = socket2::TcpKeepalive::new()
+            .with_time(keepalive.time)
+            .with_interval(keepalive.interval)
+            .with_retries(keepalive.retries);
+        socket2::SockRef::from(sock).set_tcp_keepalive(&params)?;
+    }
+
+    Ok(())
+}
+
+/// binds an ipv6 address that ipv4 clients can reach too, as ipv4 mapped addresses
+pub(crate) fn bind_dual_stack(addr: SocketAddr) -> io::Result<TcpListener> {
+    if !addr.is_ipv6() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "dual stack needs an ipv6 bind address",
+        ));
+    }
+
+    let socket = socket2::Socket::new(
+        socket2::Domain::IPV6,
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
+    )?;
+
+    socket.set_only_v6(false)?;
+    // same as what TcpListener::bind does
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => addr.fmt(f),
+                Err(_) => f.write_str("an unknown address"),
+            },
+            Listener::Unix { path, .. } => path.display().fmt(f),
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        // otherwise the next bind to the same path fails with the address in use
+        if let Listener::Unix { path, .. } = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// an accepted socket, encrypted or not
+#[derive(Debug)]
+pub(crate) enum Socket {
+    Tcp(TcpStream),
+    // boxed since the tls state is much larger than a bare socket
+    Tls(Box<TlsStream<TcpStream>>),
+    Unix(UnixStream),
+}
+
+impl AsyncRead for Socket {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(sock) => Pin::new(sock).poll_read(cx, buf),
+            Socket::Tls(sock) => Pin::new(sock).poll_read(cx, buf),
+            Socket::Unix(sock) => Pin::new(sock).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Socket {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Socket::Tcp(sock) => Pin::new(sock).poll_write(cx, buf),
+            Socket::Tls(sock) => Pin::new(sock).poll_write(cx, buf),
+            Socket::Unix(sock) => Pin::new(sock).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(sock) => Pin::new(sock).poll_flush(cx),
+            Socket::Tls(sock) => Pin::new(sock).poll_flush(cx),
+            Socket::Unix(sock) => Pin::new(sock).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(sock) => Pin::new(sock).poll_shutdown(cx),
+            Socket::Tls(sock) => Pin::new(sock).poll_shutdown(cx),
+            Socket::Unix(sock) => Pin::new(sock).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::{collections::HashMap, io, net::SocketAddr, time::Duration};
+
+use bytes::Bytes;
+use tokio::{net::UdpSocket, time::Instant};
+use tracing::debug;
+
+use crate::{dedup::RecentMessages, rate_limit::TokenBucket};
+
+/// how long a sender is remembered without a peer ttl, and at least that long with one
+///
+/// its rate limit and recent messages go with it
+const SENDER_TTL: Duration = Duration::from_secs(60);
+
+/// what's kept about whoever sent a datagram, the same limits apply to them as to a connection
+#[derive(Debug)]
+pub(crate) struct Sender {
+    last_seen: Instant,
+    pub(crate) bucket: TokenBucket,
+    pub(crate) recent: RecentMessages,
+}
+
+/// datagrams from senders that can't hold a connection open, e.g. sensors
+///
+/// delivery is best effort both ways: there's no `LOGIN`, nothing is retried
+/// and a datagram lost or dropped for a full socket buffer is simply gone
+pub(crate) struct Udp {
+    socket: UdpSocket,
+    /// every datagram lands here first, one byte longer than allowed to tell those that are too long
+    buf: Vec<u8>,
+    /// everyone heard from lately, those within the peer ttl are relayed to
+    senders: HashMap<SocketAddr, Sender>,
+    peer_ttl: Option<Duration>,
+    last_pruned: Instant,
+}
+
+impl Udp {
+    pub(crate) fn new(socket: UdpSocket, max_length: usize, peer_ttl: Option<Duration>) -> Self {
+        Udp {
+            socket,
+            buf: vec![0; max_length + 1],
+            senders: HashMap::new(),
+            peer_ttl,
+            last_pruned: Instant::now(),
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// the next datagram and who sent it, `None` in place of one that was too long
+    ///
+    /// cancel safe, nothing is lost if another event comes first
+    pub(crate) async fn recv(&mut self) -> io::Result<(SocketAddr, Option<Bytes>)> {
+        let (len, from) = self.socket.recv_from(&mut self.buf).await?;
+        let content = (len < self.buf.len()).then(|| Bytes::copy_from_slice(&self.buf[..len]));
+        Ok((from, content))
+    }
+
+    /// notes that `from` was just heard from, it's only a peer once it's let in
+    ///
+    /// senders that went quiet are forgotten every so often
+    pub(crate) fn sender(&mut self, from: SocketAddr) -> &mut Sender {
+        let now = Instant::now();
+        if now.duration_since(self.last_pruned) >= SENDER_TTL {
+            let ttl = self.peer_ttl.map_or(SENDER_TTL, |ttl| ttl.max(SENDER_TTL));
+            self.senders
+                .retain(|_, sender| now.duration_since(sender.last_seen) < ttl);
+            self.last_pruned = now;
+        }
+
+        let sender = self.senders.entry(from).or_insert_with(|| Sender {
+            last_seen: now,
+            bucket: TokenBucket::new(),
+            recent: RecentMessages::default(),
+        });
+        sender.last_seen = now;
+        sender
+    }
+
+    /// sends `msg` to every peer heard from within the ttl other than `from`
+    pub(crate) fn relay(&mut self, msg: &[u8], from: SocketAddr) {
+        let Some(ttl) = self.peer_ttl else {
+            return;
+        };
+
+        let peers = self
+            .senders
+            .iter()
+            .filter(|(peer, sender)| **peer != from && sender.last_seen.elapsed() < ttl);
+        for (peer, _) in peers {
+            // never waits, a full socket buffer loses the datagram like the network might
+            if let Err(e) = self.socket.try_send_to(msg, *peer) {
+                debug!("failed to relay datagram to {peer}: {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! a binary of its own, the file descriptor limit is shared by the whole process
+
+mod common;
+
+use std::time::Duration;
+
+use broadcast_server_example::test_util::TestClient;
+use common::{localhost, recv, start, QUIET_PERIOD};
+use tokio::{net::TcpSocket, task::LocalSet};
+
+fn fd_limit() -> libc::rlimit {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    assert_eq!(
+        unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) },
+        0
+    );
+    limit
+}
+
+fn set_fd_limit(limit: libc::rlimit) {
+    assert_eq!(unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) }, 0);
+}
+
+#[tokio::test]
+async fn running_out_of_file_descriptors_pauses_accepting_until_they_free_up() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            // made while there's still room, connecting needs no descriptor of its own
+            let socket = TcpSocket::new_v4().unwrap();
+
+            // the lowest free descriptor, so with the limit there the next one can't be opened
+            let next = unsafe { libc::dup(0) };
+            assert!(next >= 0);
+            unsafe { libc::close(next) };
+
+            let original = fd_limit();
+            set_fd_limit(libc::rlimit {
+                rlim_cur: next as libc::rlim_t,
+                ..original
+            });
+
+            // the kernel finishes the handshake but the server can't accept it yet
+            let stream = socket.connect(addr).await.unwrap();
+            tokio::time::sleep(QUIET_PERIOD).await;
+
+            set_fd_limit(original);
+            let b = tokio::time::timeout(Duration::from_secs(5), TestClient::from_stream(stream))
+                .await
+                .expect("server to accept again in time")
+                .unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+        })
+        .await;
+}
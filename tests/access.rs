@@ -0,0 +1,66 @@
+mod common;
+
+use std::{net::SocketAddr, time::Duration};
+
+use broadcast_server_example::test_util::TestClient;
+use common::{localhost, recv, start};
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpSocket, TcpStream},
+    task::LocalSet,
+};
+
+/// connects from `local`, any address in 127.0.0.0/8 works on loopback
+async fn connect_from(local: &str, addr: SocketAddr) -> TcpStream {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind(format!("{local}:0").parse().unwrap()).unwrap();
+    socket.connect(addr).await.unwrap()
+}
+
+/// true once the server has closed the connection without a greeting
+async fn refused(mut stream: TcpStream) -> bool {
+    let mut buf = Vec::new();
+    let read = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut buf))
+        .await
+        .expect("server to close the connection in time");
+    read.is_err() || buf.is_empty()
+}
+
+#[tokio::test]
+async fn allowed_addresses_are_let_in() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .allow(["127.0.0.1/32".parse().unwrap()])
+        .deny(["127.0.0.2/32".parse().unwrap()])
+        .build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::from_stream(connect_from("127.0.0.1", addr).await)
+                .await
+                .unwrap();
+            a.send_line("/whoami").await.unwrap();
+            assert!(recv(&mut a).await.starts_with("SELF:"));
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn denied_addresses_are_refused() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .allow(["127.0.0.0/24".parse().unwrap()])
+        .deny(["127.0.0.2/32".parse().unwrap()])
+        .build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            // denied wins over allowed
+            assert!(refused(connect_from("127.0.0.2", addr).await).await);
+            // and with an allowlist, anyone not on it is refused too
+            assert!(refused(connect_from("127.0.1.1", addr).await).await);
+        })
+        .await;
+}
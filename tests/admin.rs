@@ -0,0 +1,102 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use broadcast_server_example::{
+    config::{ClientIds, DeriveId},
+    test_util::TestClient,
+};
+use common::{localhost, recv, start};
+use tokio::{io::AsyncWriteExt, net::TcpStream, task::LocalSet};
+
+async fn connect_with(addr: SocketAddr, token: &str) -> TestClient {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(format!("AUTH {token}\n").as_bytes())
+        .await
+        .unwrap();
+
+    TestClient::from_stream(stream).await.unwrap()
+}
+
+#[tokio::test]
+async fn only_admins_may_kick() {
+    let local = LocalSet::new();
+    let config = localhost().auth_token("secret").admin_token("root").build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut admin = connect_with(addr, "root").await;
+            let mut a = connect_with(addr, "secret").await;
+            let mut b = connect_with(addr, "secret").await;
+            assert_eq!(recv(&mut admin).await, format!("JOIN:{}", a.id()));
+            assert_eq!(recv(&mut admin).await, format!("JOIN:{}", b.id()));
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            a.send_line(&format!("/kick {}", b.id())).await.unwrap();
+            assert_eq!(recv(&mut a).await, "ERR:forbidden");
+
+            admin.send_line("/kick 999").await.unwrap();
+            assert_eq!(recv(&mut admin).await, "ERR:no such peer");
+
+            admin.send_line(&format!("/kick {}", b.id())).await.unwrap();
+            assert_eq!(recv(&mut b).await, "KICKED:by admin");
+            assert_eq!(b.recv_line().await.unwrap(), None);
+
+            assert_eq!(recv(&mut a).await, format!("LEFT:{}", b.id()));
+            assert_eq!(recv(&mut admin).await, format!("LEFT:{}", b.id()));
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn only_admins_see_queues() {
+    let local = LocalSet::new();
+    let config = localhost().auth_token("secret").admin_token("root").build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut admin = connect_with(addr, "root").await;
+            let mut a = connect_with(addr, "secret").await;
+            assert_eq!(recv(&mut admin).await, format!("JOIN:{}", a.id()));
+
+            a.send_line("/list queues").await.unwrap();
+            assert_eq!(recv(&mut a).await, "ERR:forbidden");
+
+            admin.send_line("/list queues").await.unwrap();
+            assert_eq!(
+                recv(&mut admin).await,
+                format!("PEERS:{} q=0 buf=0", a.id())
+            );
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn custom_ids_can_come_from_the_token() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .auth_token("secret")
+        .admin_token("root")
+        .client_ids(ClientIds::Custom(DeriveId::new(|addr, token| {
+            assert!(addr.is_some());
+            match token {
+                Some("root") => 1,
+                Some(_) => 2,
+                None => 3,
+            }
+        })))
+        .build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let admin = connect_with(addr, "root").await;
+            assert_eq!(admin.id().to_string(), "1");
+            let client = connect_with(addr, "secret").await;
+            assert_eq!(client.id().to_string(), "2");
+        })
+        .await;
+}
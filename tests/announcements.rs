@@ -0,0 +1,38 @@
+mod common;
+
+use broadcast_server_example::{server::Server, test_util::TestClient};
+use common::{localhost, recv};
+use tokio::{sync::mpsc, task::LocalSet};
+
+#[tokio::test]
+async fn announcements_reach_everyone_in_every_room() {
+    let server = Server::bind(localhost().build()).await.unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let (announce, announcements) = mpsc::channel(1);
+    let local = LocalSet::new();
+    local.spawn_local(server.run(announcements, std::future::pending()));
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+            b.send_line("/join lobby").await.unwrap();
+            assert_eq!(recv(&mut b).await, "OK:joined lobby");
+            assert!(recv(&mut a).await.starts_with("LEFT:"));
+
+            announce
+                .send("restarting in 5 minutes".to_owned())
+                .await
+                .unwrap();
+            assert_eq!(recv(&mut a).await, "SYSTEM:restarting in 5 minutes");
+            assert_eq!(recv(&mut b).await, "SYSTEM:restarting in 5 minutes");
+
+            // with nothing left to announce the server carries on as before
+            drop(announce);
+            a.send_line("/whoami").await.unwrap();
+            assert!(recv(&mut a).await.starts_with("SELF:"));
+        })
+        .await;
+}
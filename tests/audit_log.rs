@@ -0,0 +1,77 @@
+mod common;
+
+use std::{path::Path, time::Duration};
+
+use broadcast_server_example::{server::Server, test_util::TestClient};
+use common::{localhost, recv};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::LocalSet,
+};
+
+/// runs a server logging to `path`, has `line` broadcast and waits for the server to stop
+async fn broadcast_one(path: &Path, line: &str) -> String {
+    let server = Server::bind(localhost().log_file(path).build())
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let (_, announcements) = mpsc::channel(1);
+    let (stop, stopped) = oneshot::channel::<()>();
+    let local = LocalSet::new();
+    let running = local.spawn_local(server.run(announcements, async {
+        let _ = stopped.await;
+    }));
+
+    local
+        .run_until(async {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+            b.send_line(line).await.unwrap();
+            assert!(recv(&mut a).await.starts_with("MESSAGE:"));
+
+            // everything queued is written out as the server stops
+            stop.send(()).unwrap();
+            tokio::time::timeout(Duration::from_secs(10), running)
+                .await
+                .expect("server to stop in time")
+                .unwrap()
+                .unwrap();
+            b.id().to_string()
+        })
+        .await
+}
+
+#[tokio::test]
+async fn broadcast_messages_are_appended_to_the_log_file() {
+    let dir = std::env::temp_dir().join(format!("audit-log-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("audit.log");
+    // left behind if an earlier run panicked
+    let _ = std::fs::remove_file(&path);
+
+    let first = broadcast_one(&path, "tab\there").await;
+    // a restart picks up where the last run left off rather than truncating
+    let second = broadcast_one(&path, "again").await;
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    let records: Vec<Vec<&str>> = log
+        .lines()
+        .map(|line| line.splitn(5, ' ').collect())
+        .collect();
+    assert_eq!(records.len(), 2, "{log}");
+
+    for (record, (from, content)) in records
+        .iter()
+        .zip([(&first, "tab\\there"), (&second, "again")])
+    {
+        assert_eq!(record[0], "global", "{log}");
+        assert_eq!(record[1], "0", "{log}");
+        assert!(record[2].parse::<u128>().is_ok(), "{log}");
+        assert_eq!(record[3], from.as_str(), "{log}");
+        assert_eq!(record[4], content, "{log}");
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
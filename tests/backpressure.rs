@@ -0,0 +1,209 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use broadcast_server_example::{
+    config::BackpressurePolicy,
+    server::{ServeError, Server},
+    test_util::TestClient,
+};
+use common::{localhost, recv, start, QUIET_PERIOD};
+use tokio::{net::TcpSocket, task::LocalSet};
+
+/// a lot more than the kernel buffers between the server and a client hold,
+/// so a client that stops reading is sure to back up into its queue
+const FLOOD_LEN: usize = 3000;
+const FILLER_LEN: usize = 4000;
+
+/// connects a client with as little kernel buffering as possible, that won't read until told to
+async fn stalled(addr: SocketAddr) -> TestClient {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.set_recv_buffer_size(4096).unwrap();
+
+    TestClient::from_stream(socket.connect(addr).await.unwrap())
+        .await
+        .unwrap()
+}
+
+/// sends `FLOOD_LEN` lines, each starting with its own five digit index,
+/// and waits for the server to have handled all of them,
+/// returning whatever else the sender was sent in the meantime
+async fn flood(sender: &mut TestClient) -> Vec<String> {
+    let filler = "x".repeat(FILLER_LEN);
+
+    for i in 0..FLOOD_LEN {
+        sender.send_line(&format!("{i:05}{filler}")).await.unwrap();
+    }
+
+    // replies come in order, so once this is answered every line above was handled,
+    // anything before it is the slow client being dropped
+    sender.send_line("/stats").await.unwrap();
+
+    let mut received = Vec::new();
+    loop {
+        let line = recv(sender).await;
+
+        if line.starts_with("STATS:") {
+            return received;
+        }
+
+        received.push(line);
+    }
+}
+
+/// the indices of the flooded messages `client` still receives,
+/// and whether the server closed the connection
+async fn drain(client: &mut TestClient) -> (Vec<usize>, bool) {
+    let mut indices = Vec::new();
+
+    loop {
+        let line = match tokio::time::timeout(QUIET_PERIOD, client.recv_line()).await {
+            Ok(Ok(Some(line))) => line,
+            Ok(_) => return (indices, true),
+            Err(_) => return (indices, false),
+        };
+
+        // `MESSAGE:<seq>:<from> <sent_at> <content>`
+        if let Some(content) = line.strip_prefix("MESSAGE:") {
+            let content = content.splitn(3, ' ').nth(2).unwrap();
+            indices.push(content[..5].parse().unwrap());
+        }
+    }
+}
+
+/// floods a stalled client and returns what it eventually gets
+async fn flood_stalled(policy: BackpressurePolicy) -> (Vec<usize>, bool) {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().backpressure(policy).build()).await;
+
+    local
+        .run_until(async move {
+            let mut slow = stalled(addr).await;
+            let mut sender = TestClient::connect(addr).await.unwrap();
+
+            // the sender is kept alive, it leaving would be news to the slow client too
+            let flooding = tokio::task::spawn_local(async move {
+                flood(&mut sender).await;
+                sender
+            });
+
+            if policy == BackpressurePolicy::Block {
+                // the server is waiting on us, so the flood can't finish before we read
+                tokio::time::sleep(QUIET_PERIOD).await;
+                let drained = drain(&mut slow).await;
+                let _sender = flooding.await.unwrap();
+                drained
+            } else {
+                let _sender = flooding.await.unwrap();
+                drain(&mut slow).await
+            }
+        })
+        .await
+}
+
+fn is_increasing(indices: &[usize]) -> bool {
+    indices.windows(2).all(|pair| pair[0] < pair[1])
+}
+
+#[tokio::test]
+async fn block_delivers_everything() {
+    let (indices, closed) = flood_stalled(BackpressurePolicy::Block).await;
+
+    assert!(!closed);
+    assert_eq!(indices, (0..FLOOD_LEN).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn drop_newest_keeps_the_start() {
+    let (indices, closed) = flood_stalled(BackpressurePolicy::DropNewest).await;
+
+    assert!(!closed);
+    assert!(is_increasing(&indices));
+    assert_eq!(indices.first(), Some(&0));
+    assert!(indices.len() < FLOOD_LEN);
+    assert_ne!(indices.last(), Some(&(FLOOD_LEN - 1)));
+}
+
+#[tokio::test]
+async fn drop_oldest_keeps_the_end() {
+    let (indices, closed) = flood_stalled(BackpressurePolicy::DropOldest).await;
+
+    assert!(!closed);
+    assert!(is_increasing(&indices));
+    assert!(indices.len() < FLOOD_LEN);
+    assert_eq!(indices.last(), Some(&(FLOOD_LEN - 1)));
+}
+
+#[tokio::test]
+async fn disconnect_drops_the_client() {
+    let (indices, closed) = flood_stalled(BackpressurePolicy::Disconnect).await;
+
+    assert!(closed);
+    assert!(indices.len() < FLOOD_LEN);
+}
+
+#[tokio::test]
+async fn clients_that_stop_reading_time_out() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .backpressure(BackpressurePolicy::DropNewest)
+        .write_timeout(QUIET_PERIOD)
+        .build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let slow = stalled(addr).await;
+            let mut sender = TestClient::connect(addr).await.unwrap();
+
+            // dropping rather than disconnecting leaves the timeout as the only way out
+            let received = flood(&mut sender).await;
+
+            let left = format!("LEFT:{}", slow.id());
+            if received.contains(&left) {
+                return;
+            }
+
+            tokio::time::timeout(QUIET_PERIOD * 10, async {
+                while recv(&mut sender).await != left {}
+            })
+            .await
+            .expect("the stalled client to be dropped");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn the_global_cap_drops_even_when_blocking() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .backpressure(BackpressurePolicy::Block)
+        .max_queued_bytes(FILLER_LEN * 16)
+        .build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut slow = stalled(addr).await;
+            let mut sender = TestClient::connect(addr).await.unwrap();
+
+            // nothing reads from the slow client yet, so blocking would never let this finish
+            tokio::time::timeout(QUIET_PERIOD * 25, flood(&mut sender))
+                .await
+                .expect("the flood not to wait on the slow client");
+
+            let (indices, closed) = drain(&mut slow).await;
+            assert!(!closed);
+            assert!(is_increasing(&indices));
+            assert!(indices.len() < FLOOD_LEN);
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn outbound_queues_without_room_are_refused() {
+    let config = localhost().outbound_queue_len(0).build();
+    let Err(ServeError::InvalidConfig(_)) = Server::bind(config).await else {
+        panic!("bound with no room in the outbound queues");
+    };
+}
@@ -0,0 +1,1192 @@
+mod common;
+
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use broadcast_server_example::{
+    config::{BackpressurePolicy, ClientIds, Dedup, DeriveId, Filtered, Keepalive, RateLimit},
+    server::{Message, ServeError, Server},
+    test_util::{generate_load, TestClient},
+};
+use common::{localhost, recv, start, QUIET_PERIOD};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::{TcpSocket, TcpStream},
+    sync::{mpsc, oneshot},
+    task::LocalSet,
+};
+
+#[tokio::test]
+async fn message_reaches_others_but_not_sender() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            a.send_line("hello").await.unwrap();
+
+            let received = recv(&mut b).await;
+            assert!(
+                received.starts_with(&format!("MESSAGE:0:{} ", a.id())),
+                "{received}"
+            );
+            assert!(received.ends_with(" hello"), "{received}");
+
+            let echoed = tokio::time::timeout(QUIET_PERIOD, a.recv_line()).await;
+            assert!(echoed.is_err(), "sender got {echoed:?}");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn yielding_mid_fan_out_still_reaches_everyone_in_order() {
+    // never, every recipient, and a period that doesn't divide the number of recipients
+    for yield_every in [0, 1, 3] {
+        let local = LocalSet::new();
+        let addr = start(
+            &local,
+            localhost().broadcast_yield_every(yield_every).build(),
+        )
+        .await;
+
+        local
+            .run_until(async move {
+                let mut clients = Vec::new();
+                for _ in 0..8 {
+                    clients.push(TestClient::connect(addr).await.unwrap());
+                }
+                // everyone hears about those who connected after them
+                for i in 0..clients.len() {
+                    for j in i + 1..clients.len() {
+                        let joined = clients[j].id();
+                        assert_eq!(recv(&mut clients[i]).await, format!("JOIN:{joined}"));
+                    }
+                }
+
+                let (sender, others) = clients.split_first_mut().unwrap();
+                for n in 0..5 {
+                    sender.send_line(&format!("burst {n}")).await.unwrap();
+                }
+                for other in others {
+                    for n in 0..5 {
+                        let line = recv(other).await;
+                        assert!(line.starts_with(&format!("MESSAGE:{n}:")), "{line}");
+                        assert!(line.ends_with(&format!(" burst {n}")), "{line}");
+                    }
+                }
+            })
+            .await;
+    }
+}
+
+#[tokio::test]
+async fn shutdown_stays_prompt_while_broadcasting_to_a_thousand_clients() {
+    let config = localhost()
+        .backpressure(BackpressurePolicy::DropNewest)
+        .shutdown_timeout(Duration::from_millis(200))
+        .build();
+    let server = Server::bind(config).await.unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let (_, announcements) = mpsc::channel(1);
+    let (stop, stopped) = oneshot::channel::<()>();
+    let local = LocalSet::new();
+    let running = local.spawn_local(server.run(announcements, async {
+        let _ = stopped.await;
+    }));
+
+    local
+        .run_until(async move {
+            // none of them read, their queues fill up and the rest is dropped
+            let mut listeners = Vec::new();
+            for _ in 0..1000 {
+                listeners.push(TestClient::connect(addr).await.unwrap());
+            }
+
+            let mut sender = TestClient::connect(addr).await.unwrap();
+            let filler = "x".repeat(1000);
+            for _ in 0..200 {
+                sender.send_line(&filler).await.unwrap();
+            }
+            // well into the fan outs by now
+            tokio::time::sleep(Duration::from_millis(50)).await;
+
+            let asked = Instant::now();
+            stop.send(()).unwrap();
+            tokio::time::timeout(Duration::from_secs(5), running)
+                .await
+                .expect("server to stop in time")
+                .unwrap()
+                .unwrap();
+            let took = asked.elapsed();
+            assert!(took < Duration::from_secs(2), "took {took:?} to stop");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn echo_self_sends_senders_their_own_messages() {
+    for echo_self in [true, false] {
+        let local = LocalSet::new();
+        let addr = start(&local, localhost().echo_self(echo_self).build()).await;
+
+        local
+            .run_until(async move {
+                let mut a = TestClient::connect(addr).await.unwrap();
+                let mut b = TestClient::connect(addr).await.unwrap();
+                assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+                a.send_line("hello").await.unwrap();
+                let received = recv(&mut b).await;
+                assert!(received.ends_with(" hello"), "{received}");
+
+                if echo_self {
+                    // exactly what everyone else got
+                    assert_eq!(recv(&mut a).await, received);
+                } else {
+                    let echoed = tokio::time::timeout(QUIET_PERIOD, a.recv_line()).await;
+                    assert!(echoed.is_err(), "sender got {echoed:?}");
+                }
+            })
+            .await;
+    }
+}
+
+#[tokio::test]
+async fn sender_is_skipped_by_id_not_port() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().build()).await;
+
+    local
+        .run_until(async move {
+            // both ends of loopback accept any 127/8 address,
+            // so two peers can share a source port as long as their ips differ
+            let first = TcpSocket::new_v4().unwrap();
+            first.set_reuseaddr(true).unwrap();
+            first.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+            let port = first.local_addr().unwrap().port();
+
+            let second = TcpSocket::new_v4().unwrap();
+            second.set_reuseaddr(true).unwrap();
+            second
+                .bind(SocketAddr::from(([127, 0, 0, 2], port)))
+                .unwrap();
+
+            let mut a = TestClient::from_stream(first.connect(addr).await.unwrap())
+                .await
+                .unwrap();
+            let mut b = TestClient::from_stream(second.connect(addr).await.unwrap())
+                .await
+                .unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            a.send_line("from a").await.unwrap();
+            assert!(recv(&mut b).await.ends_with(" from a"));
+
+            b.send_line("from b").await.unwrap();
+            assert!(recv(&mut a).await.ends_with(" from b"));
+
+            for client in [&mut a, &mut b] {
+                let echoed = tokio::time::timeout(QUIET_PERIOD, client.recv_line()).await;
+                assert!(echoed.is_err(), "sender got {echoed:?}");
+            }
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn long_line_is_refused_without_disconnecting() {
+    let local = LocalSet::new();
+    let config = localhost().max_line_length(16).build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            a.send_line(&"x".repeat(64)).await.unwrap();
+            assert_eq!(recv(&mut a).await, "ERR:line too long");
+
+            a.send_line("still here").await.unwrap();
+            assert!(recv(&mut b).await.ends_with(" still here"));
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn clients_pick_their_own_max_length_under_the_ceiling() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .max_line_length(32)
+        .default_max_line_length(16)
+        .build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+
+            a.send_line(&"x".repeat(24)).await.unwrap();
+            assert_eq!(recv(&mut a).await, "ERR:line too long");
+
+            a.send_line("MAXLEN 65536").await.unwrap();
+            assert_eq!(recv(&mut a).await, "OK:maxlen 32");
+
+            a.send_line("MAXLEN lots").await.unwrap();
+            assert_eq!(recv(&mut a).await, "ERR:invalid length");
+
+            a.send_line(&format!("/nick {}", "x".repeat(16)))
+                .await
+                .unwrap();
+            assert_eq!(recv(&mut a).await, "OK:nick set");
+
+            a.send_line(&"x".repeat(64)).await.unwrap();
+            assert_eq!(recv(&mut a).await, "ERR:line too long");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn loopback_only_answers_the_sender() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().loopback(true).build()).await;
+
+    local
+        .run_until(async move {
+            let mut observer = TestClient::connect(addr).await.unwrap();
+
+            let durations = generate_load(addr, 4, 25, "ping").await.unwrap();
+            assert_eq!(durations.len(), 100);
+
+            // the observer sees the load generators come and go but none of their messages
+            for _ in 0..8 {
+                let line = recv(&mut observer).await;
+                assert!(
+                    line.starts_with("JOIN:") || line.starts_with("LEFT:"),
+                    "got {line}"
+                );
+            }
+            let leaked = tokio::time::timeout(QUIET_PERIOD, observer.recv_line()).await;
+            assert!(leaked.is_err(), "got {leaked:?}");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn content_cannot_pass_for_the_server() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            for (sent, relayed) in [
+                ("LOGIN:9999", "LOGIN:9999"),
+                ("hi\rLEFT:0", "hiLEFT:0"),
+                ("\x1b[2Jgone\x00", "[2Jgone"),
+                ("tabs\tstay", "tabs\tstay"),
+            ] {
+                b.send_line(sent).await.unwrap();
+
+                let line = recv(&mut a).await;
+                assert!(line.starts_with("MESSAGE:"), "got {line:?}");
+                assert!(line.ends_with(&format!(" {relayed}")), "got {line:?}");
+            }
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn quick_reconnects_are_told_to_slow_down() {
+    let local = LocalSet::new();
+    let config = localhost().reconnect_cooldown(QUIET_PERIOD * 2).build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let first = TestClient::connect(addr).await.unwrap();
+            drop(first);
+
+            let err = TestClient::connect(addr).await.unwrap_err();
+            assert!(err.to_string().contains("ERR:slow down"), "{err}");
+
+            tokio::time::sleep(QUIET_PERIOD * 2).await;
+            TestClient::connect(addr).await.unwrap();
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn long_messages_are_not_relayed_but_commands_still_work() {
+    let local = LocalSet::new();
+    let config = localhost().max_message_bytes(8).build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            b.send_line("far too long").await.unwrap();
+            assert_eq!(recv(&mut b).await, "ERR:message too long");
+
+            b.send_line("/nick longer_than_8").await.unwrap();
+            assert_eq!(recv(&mut b).await, "OK:nick set");
+
+            b.send_line("short").await.unwrap();
+            assert!(recv(&mut a).await.ends_with(" short"));
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn tagged_messages_reach_only_that_tag() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().max_message_bytes(16).build()).await;
+
+    local
+        .run_until(async move {
+            let mut human = TestClient::connect(addr).await.unwrap();
+            let mut bot = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut human).await, format!("JOIN:{}", bot.id()));
+
+            bot.send_line("TAG bot").await.unwrap();
+            assert_eq!(recv(&mut bot).await, "OK:tag set");
+            bot.send_line("TAG two words").await.unwrap();
+            assert_eq!(recv(&mut bot).await, "ERR:invalid tag");
+            // either would break up `PEERS`
+            for tag in ["a,b", "x#y"] {
+                bot.send_line(&format!("TAG {tag}")).await.unwrap();
+                assert_eq!(recv(&mut bot).await, "ERR:invalid tag");
+            }
+
+            human.send_line("/list").await.unwrap();
+            assert_eq!(recv(&mut human).await, format!("PEERS:{}#bot", bot.id()));
+
+            human.send_line("/msg-tag bot beep").await.unwrap();
+            assert!(recv(&mut bot).await.ends_with(" beep"));
+
+            // held to the same limits as anything else that's relayed
+            human
+                .send_line("/msg-tag bot far too long for the limit")
+                .await
+                .unwrap();
+            assert_eq!(recv(&mut human).await, "ERR:message too long");
+
+            human.send_line("/msg-tag human boop").await.unwrap();
+            bot.send_line("done").await.unwrap();
+            // tagged messages don't use up the room's numbering
+            let done = recv(&mut human).await;
+            assert!(done.starts_with("MESSAGE:0:"), "{done}");
+            assert!(done.ends_with(" done"), "{done}");
+            let leaked = tokio::time::timeout(QUIET_PERIOD, bot.recv_line()).await;
+            assert!(leaked.is_err(), "got {leaked:?}");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn observers_only_listen() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().build()).await;
+
+    local
+        .run_until(async move {
+            let mut chatter = TestClient::connect(addr).await.unwrap();
+            let mut observer = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut chatter).await, format!("JOIN:{}", observer.id()));
+
+            observer.send_line("MODE lurker").await.unwrap();
+            assert_eq!(recv(&mut observer).await, "ERR:invalid mode");
+            observer.send_line("MODE observer").await.unwrap();
+            assert_eq!(recv(&mut observer).await, "OK:mode observer");
+            assert_eq!(recv(&mut chatter).await, format!("LEFT:{}", observer.id()));
+
+            chatter.send_line("/list").await.unwrap();
+            assert_eq!(recv(&mut chatter).await, "PEERS:");
+
+            chatter.send_line("anyone there").await.unwrap();
+            assert!(recv(&mut observer).await.ends_with(" anyone there"));
+
+            observer.send_line("yes").await.unwrap();
+            assert_eq!(recv(&mut observer).await, "ERR:read only");
+            let overheard = tokio::time::timeout(QUIET_PERIOD, chatter.recv_line()).await;
+            assert!(overheard.is_err(), "got {overheard:?}");
+
+            // nobody is told a second time
+            drop(observer);
+            let left = tokio::time::timeout(QUIET_PERIOD, chatter.recv_line()).await;
+            assert!(left.is_err(), "got {left:?}");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn acks_count_the_peers_reached() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            let mut c = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", c.id()));
+            assert_eq!(recv(&mut b).await, format!("JOIN:{}", c.id()));
+
+            c.send_line("/join elsewhere").await.unwrap();
+            assert_eq!(recv(&mut c).await, "OK:joined elsewhere");
+            assert_eq!(recv(&mut a).await, format!("LEFT:{}", c.id()));
+            assert_eq!(recv(&mut b).await, format!("LEFT:{}", c.id()));
+
+            a.send_line("/ack-send anyone").await.unwrap();
+            assert!(recv(&mut b).await.ends_with(" anyone"));
+            assert_eq!(recv(&mut a).await, "ACK:1");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn new_clients_are_shown_the_motd() {
+    let local = LocalSet::new();
+    let config = localhost().motd("be nice\nno spam\n").build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, "MOTD:be nice");
+            assert_eq!(recv(&mut a).await, "MOTD:no spam");
+
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+            assert_eq!(recv(&mut b).await, "MOTD:be nice");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn repeats_within_the_window_are_dropped() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .dedup(Dedup {
+            window: Duration::from_secs(60),
+            cache_size: 2,
+        })
+        .build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            for line in ["one", "one", "two", "one", "three", "one"] {
+                a.send_line(line).await.unwrap();
+            }
+
+            // only the last two messages are remembered, so `one` gets through again at the end
+            for expected in ["one", "two", "three", "one"] {
+                let line = recv(&mut b).await;
+                assert!(line.ends_with(&format!(" {expected}")), "{line}");
+            }
+            let repeated = tokio::time::timeout(QUIET_PERIOD, b.recv_line()).await;
+            assert!(repeated.is_err(), "got {repeated:?}");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn filters_can_rewrite_or_reject_messages() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .filter(|msg| match msg {
+            "darn it" => Filtered::Rewrite("d**n it".to_owned()),
+            "buy now" => Filtered::Reject,
+            _ => Filtered::Pass,
+        })
+        .build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            a.send_line("darn it").await.unwrap();
+            let line = recv(&mut b).await;
+            assert!(line.ends_with(" d**n it"), "{line}");
+
+            a.send_line("buy now").await.unwrap();
+            assert_eq!(recv(&mut a).await, "ERR:blocked");
+
+            // the next thing b hears is what came after the rejected message
+            a.send_line("sorry").await.unwrap();
+            let line = recv(&mut b).await;
+            assert!(line.ends_with(" sorry"), "{line}");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn ids_can_come_from_the_peer_port() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().client_ids(ClientIds::Port).build()).await;
+
+    local
+        .run_until(async move {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let port = stream.local_addr().unwrap().port();
+            let a = TestClient::from_stream(stream).await.unwrap();
+
+            assert_eq!(a.id().to_string(), port.to_string());
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn clients_with_a_clashing_id_are_turned_away() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .client_ids(ClientIds::Custom(DeriveId::new(|_, _| 7)))
+        .build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            assert_eq!(a.id().to_string(), "7");
+
+            let mut b = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            let mut line = String::new();
+            b.read_line(&mut line).await.unwrap();
+            assert_eq!(line, "ERR:id in use\n");
+
+            let joined = tokio::time::timeout(QUIET_PERIOD, a.recv_line()).await;
+            assert!(joined.is_err(), "got {joined:?}");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn bind_errors_say_which_address() {
+    let taken = Server::bind(localhost().build()).await.unwrap();
+    let addr = taken.local_addr().unwrap();
+
+    let err = Server::bind(localhost().bind([addr]).build())
+        .await
+        .err()
+        .expect("the address to be taken");
+
+    assert!(
+        matches!(err, ServeError::Bind { addr: failed, .. } if failed == addr),
+        "{err:?}"
+    );
+    assert!(
+        err.to_string()
+            .starts_with(&format!("failed to bind {addr}: ")),
+        "{err}"
+    );
+}
+
+#[tokio::test]
+async fn quit_says_goodbye_and_tells_the_others() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            b.send_line("/quit").await.unwrap();
+            assert_eq!(recv(&mut b).await, "BYE:quit");
+            assert_eq!(b.recv_line().await.unwrap(), None);
+
+            assert_eq!(recv(&mut a).await, format!("LEFT:{}", b.id()));
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn joiners_are_sent_recent_history() {
+    let local = LocalSet::new();
+    let config = localhost().history_len(2).build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+
+            for line in ["one", "two", "three"] {
+                a.send_line(line).await.unwrap();
+            }
+            // replies come in order, so once this is answered every message above was handled
+            a.send_line("/stats").await.unwrap();
+            assert!(recv(&mut a).await.starts_with("STATS:"));
+
+            let mut b = TestClient::connect(addr).await.unwrap();
+            let first = recv(&mut b).await;
+            let second = recv(&mut b).await;
+            assert!(
+                first.starts_with("HISTORY:1:") && first.ends_with(" two"),
+                "{first}"
+            );
+            assert!(
+                second.starts_with("HISTORY:2:") && second.ends_with(" three"),
+                "{second}"
+            );
+
+            a.send_line("four").await.unwrap();
+            let live = recv(&mut b).await;
+            assert!(
+                live.starts_with("MESSAGE:3:") && live.ends_with(" four"),
+                "{live}"
+            );
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn shutdown_says_goodbye() {
+    let config = localhost().shutdown_timeout(Duration::from_secs(1)).build();
+    let server = Server::bind(config).await.unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let (_, announcements) = mpsc::channel(1);
+    let (stop, stopped) = oneshot::channel::<()>();
+    let local = LocalSet::new();
+    let running = local.spawn_local(server.run(announcements, async {
+        let _ = stopped.await;
+    }));
+
+    local
+        .run_until(async move {
+            let mut client = TestClient::connect(addr).await.unwrap();
+
+            stop.send(()).unwrap();
+            assert_eq!(recv(&mut client).await, "BYE:server shutting down");
+            assert_eq!(client.recv_line().await.unwrap(), None);
+
+            tokio::time::timeout(Duration::from_secs(5), running)
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn shutdown_reports_what_was_served() {
+    let server = Server::bind(localhost().build()).await.unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let (_, announcements) = mpsc::channel(1);
+    let (stop, stopped) = oneshot::channel::<()>();
+    let local = LocalSet::new();
+    let running = local.spawn_local(server.run(announcements, async {
+        let _ = stopped.await;
+    }));
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+            drop(b);
+            assert!(recv(&mut a).await.starts_with("LEFT:"));
+
+            let mut c = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", c.id()));
+            a.send_line("one").await.unwrap();
+            a.send_line("two").await.unwrap();
+            recv(&mut c).await;
+            recv(&mut c).await;
+
+            stop.send(()).unwrap();
+            let stats = tokio::time::timeout(Duration::from_secs(5), running)
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(stats.connections_served, 3);
+            assert_eq!(stats.peak_connections, 2);
+            assert_eq!(stats.messages_relayed, 2);
+            assert!(stats.uptime > Duration::ZERO);
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn listeners_share_one_pool() {
+    let config = localhost()
+        .bind([
+            "127.0.0.1:0".parse().unwrap(),
+            "127.0.0.1:0".parse().unwrap(),
+        ])
+        .build();
+    let server = Server::bind(config).await.unwrap();
+    let addrs = server.local_addrs();
+    assert_eq!(addrs.len(), 2);
+
+    let (_, announcements) = mpsc::channel(1);
+    let local = LocalSet::new();
+    local.spawn_local(server.run(announcements, std::future::pending()));
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addrs[0]).await.unwrap();
+            let mut b = TestClient::connect(addrs[1]).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            b.send_line("across").await.unwrap();
+            assert!(recv(&mut a).await.ends_with(" across"));
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn sequence_numbers_count_per_room() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            a.send_line("first").await.unwrap();
+            assert!(recv(&mut b).await.starts_with("MESSAGE:0:"));
+
+            // nothing said elsewhere shows up as a gap here
+            b.send_line("/join side").await.unwrap();
+            assert_eq!(recv(&mut b).await, "OK:joined side");
+            b.send_line("elsewhere").await.unwrap();
+            b.send_line("/leave").await.unwrap();
+            assert_eq!(recv(&mut b).await, "OK:joined global");
+
+            a.send_line("second").await.unwrap();
+            assert!(recv(&mut b).await.starts_with("MESSAGE:1:"));
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn messages_can_be_numbered_per_sender() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().number_per_sender(true).build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            a.send_line("/nick al#1").await.unwrap();
+            assert_eq!(recv(&mut a).await, "ERR:invalid nick");
+            // would pass for two peers in `PEERS`
+            a.send_line("/nick al,bob").await.unwrap();
+            assert_eq!(recv(&mut a).await, "ERR:invalid nick");
+            a.send_line("/nick alice").await.unwrap();
+            assert_eq!(recv(&mut a).await, "OK:nick set");
+
+            a.send_line("one").await.unwrap();
+            b.send_line("hello").await.unwrap();
+            a.send_line("two").await.unwrap();
+
+            let Some(Message::Chat { sender_seq, .. }) = a.recv_message().await.unwrap() else {
+                panic!("expected a chat message");
+            };
+            assert_eq!(sender_seq, Some(0));
+
+            for expected in [0, 1] {
+                let Some(Message::Chat {
+                    from, sender_seq, ..
+                }) = b.recv_message().await.unwrap()
+                else {
+                    panic!("expected a chat message");
+                };
+                assert_eq!(from, "alice");
+                assert_eq!(sender_seq, Some(expected));
+            }
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn whoami_answers_only_the_asker() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            a.send_line("/whoami").await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("SELF:{} room=global", a.id()));
+
+            a.send_line("/nick alice").await.unwrap();
+            assert_eq!(recv(&mut a).await, "OK:nick set");
+            a.send_line("/join lobby").await.unwrap();
+            assert_eq!(recv(&mut a).await, "OK:joined lobby");
+            assert_eq!(recv(&mut b).await, format!("LEFT:{}", a.id()));
+            a.send_line("/whoami").await.unwrap();
+            assert_eq!(
+                recv(&mut a).await,
+                format!("SELF:{} nick=alice room=lobby", a.id())
+            );
+
+            let overheard = tokio::time::timeout(QUIET_PERIOD, b.recv_line()).await;
+            assert!(overheard.is_err(), "other client got {overheard:?}");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn direct_messages_reach_only_their_target() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            let mut c = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", c.id()));
+            assert_eq!(recv(&mut b).await, format!("JOIN:{}", c.id()));
+
+            a.send_line(&format!("/msg {} just for you", b.id()))
+                .await
+                .unwrap();
+            assert_eq!(recv(&mut b).await, format!("DM:{} just for you", a.id()));
+
+            for client in [&mut a, &mut c] {
+                let overheard = tokio::time::timeout(QUIET_PERIOD, client.recv_line()).await;
+                assert!(overheard.is_err(), "got {overheard:?}");
+            }
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn direct_messages_to_unknown_ids_are_refused() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            // nobody has that id, and sending to yourself doesn't count either
+            for to in ["999999".to_owned(), a.id().to_string()] {
+                a.send_line(&format!("/msg {to} anyone there"))
+                    .await
+                    .unwrap();
+                assert_eq!(recv(&mut a).await, "ERR:no such peer");
+            }
+
+            let overheard = tokio::time::timeout(QUIET_PERIOD, b.recv_line()).await;
+            assert!(overheard.is_err(), "got {overheard:?}");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn changing_rooms_tells_both_rooms() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().left_reason(true).build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            let mut c = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", c.id()));
+            assert_eq!(recv(&mut b).await, format!("JOIN:{}", c.id()));
+
+            b.send_line("/join lobby").await.unwrap();
+            assert_eq!(recv(&mut b).await, "OK:joined lobby");
+            assert_eq!(recv(&mut a).await, format!("LEFT:{} changed room", b.id()));
+            assert_eq!(recv(&mut c).await, format!("LEFT:{} changed room", b.id()));
+
+            c.send_line("/join lobby").await.unwrap();
+            assert_eq!(recv(&mut c).await, "OK:joined lobby");
+            assert_eq!(recv(&mut a).await, format!("LEFT:{} changed room", c.id()));
+            assert_eq!(recv(&mut b).await, format!("JOIN:{}", c.id()));
+
+            // already there, so there's nothing to tell anyone
+            c.send_line("/join lobby").await.unwrap();
+            assert_eq!(recv(&mut c).await, "OK:joined lobby");
+            a.send_line("/leave").await.unwrap();
+            assert_eq!(recv(&mut a).await, "OK:joined global");
+
+            b.send_line("/leave").await.unwrap();
+            assert_eq!(recv(&mut b).await, "OK:joined global");
+            assert_eq!(recv(&mut c).await, format!("LEFT:{} changed room", b.id()));
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            for client in [&mut a, &mut b, &mut c] {
+                let spurious = tokio::time::timeout(QUIET_PERIOD, client.recv_line()).await;
+                assert!(spurious.is_err(), "got {spurious:?}");
+            }
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn arrivals_and_departures_are_only_told_to_the_room() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            a.send_line("/join lobby").await.unwrap();
+            assert_eq!(recv(&mut a).await, "OK:joined lobby");
+            let mut b = TestClient::connect(addr).await.unwrap();
+
+            // no JOIN for a, b started out in the default room
+            a.send_line("/whoami").await.unwrap();
+            assert!(recv(&mut a).await.starts_with("SELF:"));
+
+            b.send_line("/quit").await.unwrap();
+            assert_eq!(recv(&mut b).await, "BYE:quit");
+            drop(b);
+
+            let mut c = TestClient::connect(addr).await.unwrap();
+            c.send_line("/join lobby").await.unwrap();
+            assert_eq!(recv(&mut c).await, "OK:joined lobby");
+            let c_id = c.id();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{c_id}"));
+
+            // b's LEFT never came either, or it would have arrived first
+            drop(c);
+            assert_eq!(recv(&mut a).await, format!("LEFT:{c_id}"));
+            let spurious = tokio::time::timeout(QUIET_PERIOD, a.recv_line()).await;
+            assert!(spurious.is_err(), "got {spurious:?}");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn joining_a_room_replays_only_its_history() {
+    let local = LocalSet::new();
+    let config = localhost().history_len(4).build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            a.send_line("in global").await.unwrap();
+            a.send_line("/join lobby").await.unwrap();
+            assert_eq!(recv(&mut a).await, "OK:joined lobby");
+            a.send_line("in lobby").await.unwrap();
+            a.send_line("/stats").await.unwrap();
+            assert!(recv(&mut a).await.starts_with("STATS:"));
+
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert!(recv(&mut b).await.ends_with(" in global"));
+            b.send_line("/join lobby").await.unwrap();
+            assert_eq!(recv(&mut b).await, "OK:joined lobby");
+            let replayed = recv(&mut b).await;
+            assert!(
+                replayed.starts_with("HISTORY:0:") && replayed.ends_with(" in lobby"),
+                "{replayed}"
+            );
+
+            let more = tokio::time::timeout(QUIET_PERIOD, b.recv_line()).await;
+            assert!(more.is_err(), "got {more:?}");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn empty_rooms_are_forgotten_after_the_ttl() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .history_len(4)
+        .history_ttl(Duration::ZERO)
+        .build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            a.send_line("/join lobby").await.unwrap();
+            assert_eq!(recv(&mut a).await, "OK:joined lobby");
+            a.send_line("gone soon").await.unwrap();
+            a.send_line("/leave").await.unwrap();
+            assert_eq!(recv(&mut a).await, "OK:joined global");
+
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+            b.send_line("/join lobby").await.unwrap();
+            assert_eq!(recv(&mut b).await, "OK:joined lobby");
+
+            let replayed = tokio::time::timeout(QUIET_PERIOD, b.recv_line()).await;
+            assert!(replayed.is_err(), "got {replayed:?}");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn silent_clients_are_disconnected_once_idle() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .idle_timeout(Duration::from_millis(500))
+        .left_reason(true)
+        .build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            // a keeps itself busy while b says nothing
+            let mut busy = tokio::time::interval(Duration::from_millis(100));
+            let left = loop {
+                tokio::select! {
+                    _ = busy.tick() => a.send_line("/whoami").await.unwrap(),
+                    line = recv(&mut a) => if !line.starts_with("SELF:") {
+                        break line;
+                    },
+                }
+            };
+            assert_eq!(left, format!("LEFT:{} idle", b.id()));
+            assert_eq!(b.recv_line().await.unwrap(), None);
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn a_zero_idle_timeout_is_refused() {
+    let config = localhost().idle_timeout(Duration::ZERO).build();
+    let Err(ServeError::InvalidConfig(_)) = Server::bind(config).await else {
+        panic!("bound with a zero idle timeout");
+    };
+}
+
+#[tokio::test]
+async fn rate_limits_that_cant_work_are_refused() {
+    let limits = [
+        (f64::NAN, 5),
+        (f64::INFINITY, 5),
+        (0.0, 5),
+        (-1.0, 5),
+        (1.0, 0),
+    ];
+
+    for (per_second, burst) in limits {
+        let config = localhost()
+            .rate_limit(RateLimit { per_second, burst })
+            .build();
+        let Err(ServeError::InvalidConfig(_)) = Server::bind(config).await else {
+            panic!("bound with {per_second} per second and a burst of {burst}");
+        };
+    }
+}
+
+#[tokio::test]
+async fn clients_that_stop_answering_pings_are_disconnected() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .keepalive(Keepalive {
+            interval: Duration::from_millis(100),
+            max_missed: 2,
+        })
+        .left_reason(true)
+        .build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            // a answers every ping, b never does
+            let mut pings = 0;
+            let left = loop {
+                let line = recv(&mut a).await;
+                if line != "PING" {
+                    break line;
+                }
+                pings += 1;
+                a.send_line("PONG").await.unwrap();
+            };
+            assert_eq!(left, format!("LEFT:{} unresponsive", b.id()));
+            assert!(pings >= 2, "only pinged {pings} times");
+
+            // and stays connected for as long as it keeps answering
+            for _ in 0..3 {
+                assert_eq!(recv(&mut a).await, "PING");
+                a.send_line("PONG").await.unwrap();
+            }
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn a_zero_keepalive_interval_is_refused() {
+    let config = localhost()
+        .keepalive(Keepalive {
+            interval: Duration::ZERO,
+            max_missed: 2,
+        })
+        .build();
+    let Err(ServeError::InvalidConfig(_)) = Server::bind(config).await else {
+        panic!("bound with a zero keepalive interval");
+    };
+}
+
+#[tokio::test]
+async fn left_can_say_why() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().left_reason(true).build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            let mut c = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", c.id()));
+
+            b.send_line("/quit").await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("LEFT:{} quit", b.id()));
+
+            // anything left unread would turn closing into a reset
+            assert_eq!(recv(&mut c).await, format!("LEFT:{} quit", b.id()));
+            let c_id = c.id();
+            drop(c);
+            assert_eq!(recv(&mut a).await, format!("LEFT:{c_id} closed"));
+        })
+        .await;
+}
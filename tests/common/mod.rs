@@ -0,0 +1,42 @@
+// every test binary pulls this in but none of them use all of it
+#![allow(dead_code)]
+
+use std::{net::SocketAddr, time::Duration};
+
+use broadcast_server_example::{
+    config::{ServerConfig, ServerConfigBuilder},
+    server::Server,
+    test_util::TestClient,
+};
+use tokio::{sync::mpsc, task::LocalSet};
+
+/// long enough for anything the server meant to send to have arrived
+pub const QUIET_PERIOD: Duration = Duration::from_millis(200);
+
+/// binds `config` and runs the server on `local` until the test ends
+///
+/// the server future isn't Send, so it runs on the test's thread alongside the clients
+pub async fn start(local: &LocalSet, config: ServerConfig) -> SocketAddr {
+    let server = Server::bind(config).await.unwrap();
+    let addr = server.local_addr().unwrap();
+
+    // the announcement sender is dropped straight away, tests have nothing to announce
+    let (_, announcements) = mpsc::channel(1);
+    local.spawn_local(server.run(announcements, std::future::pending()));
+
+    addr
+}
+
+/// the next line, panicking if it doesn't arrive or the connection closes
+pub async fn recv(client: &mut TestClient) -> String {
+    tokio::time::timeout(Duration::from_secs(5), client.recv_line())
+        .await
+        .expect("server to answer in time")
+        .unwrap()
+        .expect("connection to stay open")
+}
+
+/// listens on an ephemeral port so tests can run in parallel
+pub fn localhost() -> ServerConfigBuilder {
+    ServerConfig::builder().bind(["127.0.0.1:0".parse().unwrap()])
+}
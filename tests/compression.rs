@@ -0,0 +1,63 @@
+mod common;
+
+use std::io::Read;
+
+use broadcast_server_example::test_util::TestClient;
+use common::{localhost, start};
+use flate2::read::DeflateDecoder;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    task::LocalSet,
+};
+
+/// reads one length prefixed frame and inflates it
+async fn recv_deflated(stream: &mut BufReader<TcpStream>) -> String {
+    let len = stream.read_u32().await.unwrap();
+    let mut deflated = vec![0; len as usize];
+    stream.read_exact(&mut deflated).await.unwrap();
+
+    let mut inflated = String::new();
+    DeflateDecoder::new(&deflated[..])
+        .read_to_string(&mut inflated)
+        .unwrap();
+    inflated
+}
+
+#[tokio::test]
+async fn compressed_clients_get_deflated_frames() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().build()).await;
+
+    local
+        .run_until(async move {
+            let mut compressed = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            let mut line = String::new();
+            compressed.read_line(&mut line).await.unwrap();
+            assert!(line.starts_with("LOGIN:"), "{line}");
+
+            compressed.write_all(b"COMPRESS on\n").await.unwrap();
+            line.clear();
+            compressed.read_line(&mut line).await.unwrap();
+            assert_eq!(line, "OK:compress on\n");
+
+            let mut plain = TestClient::connect(addr).await.unwrap();
+            assert_eq!(
+                recv_deflated(&mut compressed).await,
+                format!("JOIN:{}", plain.id())
+            );
+
+            let blob = "lorem ipsum ".repeat(500);
+            plain.send_line(&blob).await.unwrap();
+            let received = recv_deflated(&mut compressed).await;
+            assert!(received.ends_with(&blob), "{received}");
+
+            compressed.write_all(b"COMPRESS off\n").await.unwrap();
+            assert_eq!(recv_deflated(&mut compressed).await, "OK:compress off");
+            plain.send_line("plain again").await.unwrap();
+            line.clear();
+            compressed.read_line(&mut line).await.unwrap();
+            assert!(line.ends_with(" plain again\n"), "{line}");
+        })
+        .await;
+}
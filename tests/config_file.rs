@@ -0,0 +1,51 @@
+use std::{path::Path, time::Duration};
+
+use broadcast_server_example::config::{
+    BackpressurePolicy, ConfigFileError, ServerConfig, DEFAULT_WRITE_TIMEOUT,
+};
+
+fn example() -> ServerConfig {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/server.toml");
+    ServerConfig::from_file(path).unwrap()
+}
+
+#[test]
+fn the_example_config_loads() {
+    let config = example();
+
+    assert_eq!(config.bind.len(), 2);
+    assert_eq!(config.max_line_length, 65536);
+    assert_eq!(config.backpressure, BackpressurePolicy::DropOldest);
+    assert_eq!(config.idle_timeout, Duration::from_secs(300));
+    assert_eq!(config.keepalive.unwrap().interval, Duration::from_secs(60));
+    assert!(config.tcp_nodelay);
+    assert_eq!(config.tcp_keepalive.unwrap().time, Duration::from_secs(120));
+    assert_eq!(config.auth_token.as_deref(), Some("change me"));
+    assert!(config.tls.is_none());
+}
+
+#[test]
+fn left_out_keys_keep_their_defaults() {
+    let dir = std::env::temp_dir().join(format!("config-file-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("partial.toml");
+    std::fs::write(&path, "history_len = 5\n").unwrap();
+
+    let config = ServerConfig::from_file(&path).unwrap();
+    assert_eq!(config.history_len, 5);
+    assert_eq!(config.write_timeout, DEFAULT_WRITE_TIMEOUT);
+
+    std::fs::write(&path, "histroy_len = 5\n").unwrap();
+    let err = ServerConfig::from_file(&path).unwrap_err();
+    assert!(matches!(err, ConfigFileError::Parse { .. }), "{err}");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn missing_files_say_which() {
+    let err = ServerConfig::from_file("/does/not/exist.toml").unwrap_err();
+
+    assert!(matches!(err, ConfigFileError::Read { .. }));
+    assert!(err.to_string().contains("/does/not/exist.toml"), "{err}");
+}
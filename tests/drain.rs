@@ -0,0 +1,54 @@
+// in a binary of its own since the signal reaches every server in the process
+mod common;
+
+use std::time::Duration;
+
+use broadcast_server_example::{server::Server, test_util::TestClient};
+use common::{localhost, recv, QUIET_PERIOD};
+use tokio::{sync::mpsc, task::LocalSet};
+
+#[tokio::test]
+async fn sigterm_drains_before_stopping() {
+    let server = Server::bind(localhost().drain_on_sigterm(true).build())
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let (_, announcements) = mpsc::channel(1);
+    let local = LocalSet::new();
+    let running = local.spawn_local(server.run(announcements, std::future::pending()));
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            let status = std::process::Command::new("kill")
+                .args(["-TERM", &std::process::id().to_string()])
+                .status()
+                .unwrap();
+            assert!(status.success());
+            // signals are picked up asynchronously, give the server a moment to notice
+            tokio::time::sleep(QUIET_PERIOD).await;
+
+            // the listener stays open but nobody new is let in
+            let late = tokio::time::timeout(QUIET_PERIOD, TestClient::connect(addr)).await;
+            assert!(late.is_err(), "accepted while draining: {late:?}");
+
+            a.send_line("still serving").await.unwrap();
+            assert!(recv(&mut b).await.ends_with(" still serving"));
+
+            a.send_line("/quit").await.unwrap();
+            assert_eq!(recv(&mut a).await, "BYE:quit");
+            assert!(!running.is_finished(), "stopped with a client left");
+
+            b.send_line("/quit").await.unwrap();
+            tokio::time::timeout(Duration::from_secs(5), running)
+                .await
+                .expect("to stop once everyone left")
+                .unwrap()
+                .unwrap();
+        })
+        .await;
+}
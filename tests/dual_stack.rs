@@ -0,0 +1,57 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use broadcast_server_example::{
+    config::ServerConfig,
+    server::{ServeError, Server},
+    test_util::TestClient,
+};
+use common::recv;
+use tokio::{sync::mpsc, task::LocalSet};
+
+#[tokio::test]
+async fn ipv4_and_ipv6_clients_share_one_listener() {
+    let config = ServerConfig::builder()
+        .bind(["[::]:0".parse().unwrap()])
+        .dual_stack(true)
+        .build();
+    let server = Server::bind(config).await.unwrap();
+    let port = server.local_addr().unwrap().port();
+    let roster = server.roster();
+
+    let (_, announcements) = mpsc::channel(1);
+    let local = LocalSet::new();
+    local.spawn_local(server.run(announcements, std::future::pending()));
+
+    local
+        .run_until(async move {
+            let v4 = SocketAddr::from(([127, 0, 0, 1], port));
+            let v6 = SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], port));
+
+            let mut a = TestClient::connect(v4).await.unwrap();
+            let b = TestClient::connect(v6).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            let mut peers = roster.snapshot().await.unwrap();
+            peers.sort_by_key(|peer| peer.id);
+            // the ipv4 client shows up as an ipv4 mapped address
+            let a_ip = peers[0].addr.unwrap().ip();
+            assert_eq!(a_ip.to_canonical(), v4.ip(), "{a_ip}");
+            assert_eq!(peers[1].addr.unwrap().ip(), v6.ip());
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn dual_stack_needs_an_ipv6_address() {
+    let config = ServerConfig::builder()
+        .bind(["127.0.0.1:0".parse().unwrap()])
+        .dual_stack(true)
+        .build();
+
+    let Err(ServeError::Bind { addr, .. }) = Server::bind(config).await else {
+        panic!("bound an ipv4 address dual stack");
+    };
+    assert_eq!(addr, "127.0.0.1:0".parse().unwrap());
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use std::time::Duration;
+
+use broadcast_server_example::{
+    server::{Server, ServerEvent},
+    test_util::TestClient,
+};
+use common::{localhost, recv};
+use futures::{Stream, StreamExt};
+use tokio::{sync::mpsc, task::LocalSet};
+
+/// the next event, panicking if none arrives or the stream ends
+async fn next(events: &mut (impl Stream<Item = ServerEvent> + Unpin)) -> ServerEvent {
+    tokio::time::timeout(Duration::from_secs(5), events.next())
+        .await
+        .expect("an event in time")
+        .expect("the server to still be running")
+}
+
+#[tokio::test]
+async fn events_follow_what_happens() {
+    let server = Server::bind(localhost().build()).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let mut events = Box::pin(server.events());
+
+    let (_, announcements) = mpsc::channel(1);
+    let local = LocalSet::new();
+    local.spawn_local(server.run(announcements, std::future::pending()));
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            assert!(
+                matches!(next(&mut events).await, ServerEvent::Joined { id, addr: Some(_) } if id == a.id())
+            );
+
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+            assert!(matches!(next(&mut events).await, ServerEvent::Joined { id, .. } if id == b.id()));
+
+            a.send_line("hello").await.unwrap();
+            assert_eq!(
+                next(&mut events).await,
+                ServerEvent::Message {
+                    id: a.id(),
+                    room: "global".to_owned(),
+                    seq: 0,
+                    content: "hello".into(),
+                }
+            );
+
+            // anything left unread would turn closing into a reset
+            assert!(recv(&mut b).await.ends_with(" hello"));
+            let b_id = b.id();
+            drop(b);
+            assert_eq!(
+                next(&mut events).await,
+                ServerEvent::Left {
+                    id: b_id,
+                    reason: "closed",
+                }
+            );
+        })
+        .await;
+}
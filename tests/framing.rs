@@ -0,0 +1,105 @@
+mod common;
+
+use std::time::Duration;
+
+use broadcast_server_example::config::Framing;
+use bytes::Bytes;
+use common::{localhost, start};
+use futures::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    task::LocalSet,
+};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+/// the next line as raw bytes, without the newline
+async fn read_line(reader: &mut BufReader<TcpStream>) -> Vec<u8> {
+    let mut line = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), reader.read_until(b'\n', &mut line))
+        .await
+        .expect("server to answer in time")
+        .unwrap();
+    assert_eq!(line.pop(), Some(b'\n'), "connection closed");
+    line
+}
+
+/// the next length delimited frame
+async fn read_frame(framed: &mut Framed<TcpStream, LengthDelimitedCodec>) -> Vec<u8> {
+    tokio::time::timeout(Duration::from_secs(5), framed.next())
+        .await
+        .expect("server to answer in time")
+        .expect("connection to stay open")
+        .unwrap()
+        .to_vec()
+}
+
+#[tokio::test]
+async fn length_delimited_messages_near_the_limit_reach_peers() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .framing(Framing::LengthDelimited)
+        .max_line_length(100)
+        .build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let connect = || async {
+                Framed::new(
+                    TcpStream::connect(addr).await.unwrap(),
+                    LengthDelimitedCodec::new(),
+                )
+            };
+            let mut a = connect().await;
+            assert!(read_frame(&mut a).await.starts_with(b"LOGIN:"));
+            let mut b = connect().await;
+            assert!(read_frame(&mut b).await.starts_with(b"LOGIN:"));
+            assert!(read_frame(&mut a).await.starts_with(b"JOIN:"));
+
+            // once the header is added this no longer fits in 100 bytes
+            let content = [b'x'; 95];
+            b.send(Bytes::copy_from_slice(&content)).await.unwrap();
+            let frame = read_frame(&mut a).await;
+            assert!(frame.starts_with(b"MESSAGE:"), "{}", frame.escape_ascii());
+            assert!(frame.ends_with(&content), "{}", frame.escape_ascii());
+
+            // and the sender is still connected
+            b.send(Bytes::from_static(b"/whoami")).await.unwrap();
+            assert!(read_frame(&mut b).await.starts_with(b"SELF:"));
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn delimited_lines_carry_bytes_that_arent_utf8() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().framing(Framing::Delimited).build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            assert!(read_line(&mut a).await.starts_with(b"LOGIN:"));
+            let mut b = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            assert!(read_line(&mut b).await.starts_with(b"LOGIN:"));
+            assert!(read_line(&mut a).await.starts_with(b"JOIN:"));
+
+            b.get_mut()
+                .write_all(b"caf\xe9 \xff\xfe\x00\x1b[0m\r\n")
+                .await
+                .unwrap();
+            let line = read_line(&mut a).await;
+            assert!(line.starts_with(b"MESSAGE:"), "{}", line.escape_ascii());
+            // control bytes included
+            assert!(
+                line.ends_with(b" caf\xe9 \xff\xfe\x00\x1b[0m\r"),
+                "{}",
+                line.escape_ascii()
+            );
+
+            // still connected
+            b.get_mut().write_all(b"/whoami\n").await.unwrap();
+            assert!(read_line(&mut b).await.starts_with(b"SELF:"));
+        })
+        .await;
+}
@@ -0,0 +1,214 @@
+//! throws arbitrary lines at a running server, which must neither fall over
+//! nor let a client's line pass for anything the server didn't mean to send
+
+mod common;
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use broadcast_server_example::server::Server;
+use common::localhost;
+use proptest::{prelude::*, test_runner::TestRunner};
+use tokio::{sync::mpsc, task::LocalSet};
+
+/// how long a peer goes without hearing anything before it's taken to have everything
+const SETTLE: Duration = Duration::from_millis(50);
+
+/// runs a server on a thread of its own, the cases are driven with blocking sockets from the test's
+fn spawn_server() -> SocketAddr {
+    let (addr_tx, addr_rx) = std::sync::mpsc::channel();
+
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        // a panic anywhere in the event loop ends the thread and with it the server
+        let _ = LocalSet::new().block_on(&runtime, async move {
+            let server = Server::bind(localhost().build()).await.unwrap();
+            addr_tx.send(server.local_addr().unwrap()).unwrap();
+
+            let (_, announcements) = mpsc::channel(1);
+            server.run(announcements, std::future::pending()).await
+        });
+    });
+
+    addr_rx.recv().unwrap()
+}
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    /// as given in `LOGIN`
+    id: Vec<u8>,
+}
+
+impl Client {
+    fn connect(addr: SocketAddr) -> Self {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let mut client = Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+            id: Vec::new(),
+        };
+        let greeting = client.read_line().expect("server to greet new clients");
+        let login = greeting
+            .strip_prefix(b"LOGIN:")
+            .unwrap_or_else(|| panic!("expected a greeting, got {}", greeting.escape_ascii()));
+        client.id = login.split(|b| *b == b' ').next().unwrap().to_vec();
+
+        client
+    }
+
+    /// `None` once the connection closes or nothing arrives in time
+    fn read_line(&mut self) -> Option<Vec<u8>> {
+        let mut line = Vec::new();
+
+        match self.reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => {
+                line.pop();
+                Some(line)
+            }
+        }
+    }
+}
+
+/// lines worth throwing at the parser, many of them close to real commands
+fn line() -> impl Strategy<Value = Vec<u8>> {
+    let raw = prop::collection::vec(
+        any::<u8>().prop_filter("a line of its own", |b| *b != b'\n'),
+        0..64,
+    );
+    let command = (
+        prop::sample::select(vec![
+            "/nick ",
+            "/msg ",
+            "/msg-tag ",
+            "/join ",
+            "/leave",
+            "/list",
+            "/list queues",
+            "/stats",
+            "/whoami",
+            "/kick ",
+            "/ack-send ",
+            "/",
+            "COMPRESS ",
+            "MAXLEN ",
+            "TAG ",
+            "MODE ",
+            "HELLO ",
+            "HELLO v1 ",
+            "LOGIN:",
+            "MESSAGE:",
+            "PONG",
+        ]),
+        "\\PC{0,16}",
+    )
+        .prop_map(|(verb, rest)| format!("{verb}{rest}").into_bytes());
+
+    // cut anywhere, including through the middle of a multi byte character
+    (prop_oneof![raw, command], any::<prop::sample::Index>()).prop_map(|(mut line, cut)| {
+        if !line.is_empty() {
+            line.truncate(cut.index(line.len() + 1));
+        }
+        line
+    })
+}
+
+/// whether `line` is one of the few things a silent peer can be sent because of someone else
+fn well_formed(line: &[u8]) -> bool {
+    fn digits(field: &[u8]) -> bool {
+        !field.is_empty() && field.iter().all(u8::is_ascii_digit)
+    }
+
+    // clients that also end lines at `\r` or stop at a nul would see a second line otherwise
+    if line.iter().any(|b| b.is_ascii_control() && *b != b'\t') {
+        return false;
+    }
+
+    if let Some(rest) = line.strip_prefix(b"MESSAGE:") {
+        // `<seq>:<from> <sent_at> <content>`
+        let mut fields = rest.splitn(2, |b| *b == b':');
+        let (Some(seq), Some(rest)) = (fields.next(), fields.next()) else {
+            return false;
+        };
+        let mut fields = rest.splitn(3, |b| *b == b' ');
+        let (Some(from), Some(sent_at), Some(_)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return false;
+        };
+
+        return digits(seq) && !from.is_empty() && digits(sent_at);
+    }
+
+    if let Some(rest) = line.strip_prefix(b"LEFT:") {
+        let id = rest.split(|b| *b == b' ').next().unwrap_or_default();
+        return digits(id);
+    }
+
+    // the sender changing rooms
+    if let Some(id) = line.strip_prefix(b"JOIN:") {
+        return digits(id);
+    }
+
+    line.starts_with(b"DM:")
+}
+
+#[test]
+fn arbitrary_lines_cannot_break_the_server_or_pass_for_it() {
+    let addr = spawn_server();
+    let mut runner = TestRunner::new(ProptestConfig {
+        cases: 64,
+        ..ProptestConfig::default()
+    });
+
+    runner
+        .run(&prop::collection::vec(line(), 1..8), |lines| {
+            let mut peer = Client::connect(addr);
+            let mut sender = Client::connect(addr);
+            // the clients of earlier cases may still be sending or leaving
+            let joined = [b"JOIN:".as_slice(), &sender.id].concat();
+            loop {
+                let line = peer.read_line().unwrap_or_default();
+                if line == joined {
+                    break;
+                }
+                prop_assert!(well_formed(&line), "{}", line.escape_ascii());
+            }
+
+            // the sender may well be disconnected along the way, e.g. for `/quit`
+            for line in &lines {
+                let sent = sender.writer.write_all(&[line.as_slice(), b"\n"].concat());
+                if sent.is_err() {
+                    break;
+                }
+            }
+
+            peer.reader
+                .get_ref()
+                .set_read_timeout(Some(SETTLE))
+                .unwrap();
+            while let Some(line) = peer.read_line() {
+                prop_assert!(well_formed(&line), "peer got {}", line.escape_ascii());
+            }
+
+            // still serving whatever came before
+            let mut check = Client::connect(addr);
+            check.writer.write_all(b"/whoami\n").unwrap();
+            let whoami = check.read_line().unwrap_or_default();
+            prop_assert!(whoami.starts_with(b"SELF:"), "{}", whoami.escape_ascii());
+
+            Ok(())
+        })
+        .unwrap();
+}
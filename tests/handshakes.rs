@@ -0,0 +1,66 @@
+mod common;
+
+use broadcast_server_example::test_util::TestClient;
+use common::{localhost, recv, start, QUIET_PERIOD};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    task::LocalSet,
+};
+
+#[tokio::test]
+async fn handshakes_past_the_limit_wait_their_turn() {
+    let local = LocalSet::new();
+    let config = localhost().auth_token("secret").max_handshakes(2).build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            // these never authenticate, holding up every slot
+            let mut silent = Vec::new();
+            for _ in 0..5 {
+                silent.push(TcpStream::connect(addr).await.unwrap());
+            }
+
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(b"AUTH secret\n").await.unwrap();
+            let login = tokio::task::spawn_local(TestClient::from_stream(stream));
+
+            tokio::time::sleep(QUIET_PERIOD).await;
+            assert!(!login.is_finished(), "accepted while every slot was taken");
+
+            drop(silent);
+            let client = tokio::time::timeout(QUIET_PERIOD * 10, login)
+                .await
+                .expect("to be let in once the others left")
+                .unwrap();
+            assert!(client.is_ok(), "{client:?}");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn health_probes_are_answered_without_joining() {
+    let local = LocalSet::new();
+    let config = localhost().health_probe_window(QUIET_PERIOD).build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+
+            let mut probe = TcpStream::connect(addr).await.unwrap();
+            probe.write_all(b"HEALTH\n").await.unwrap();
+            let mut reply = String::new();
+            probe.read_to_string(&mut reply).await.unwrap();
+            assert_eq!(reply, "OK\n");
+
+            // a client that speaks first is still let in, what it said included
+            let mut b = TcpStream::connect(addr).await.unwrap();
+            b.write_all(b"hello\n").await.unwrap();
+            let b = TestClient::from_stream(b).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+            assert!(recv(&mut a).await.ends_with(" hello"));
+        })
+        .await;
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use std::{net::SocketAddr, time::Duration};
+
+use broadcast_server_example::config::Protocol;
+use common::{localhost, start};
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::{net::TcpStream, task::LocalSet};
+use tokio_util::codec::{Framed, LinesCodec};
+
+type JsonClient = Framed<TcpStream, LinesCodec>;
+
+async fn connect(addr: SocketAddr) -> JsonClient {
+    Framed::new(TcpStream::connect(addr).await.unwrap(), LinesCodec::new())
+}
+
+/// the next line as json, panicking if it doesn't arrive or doesn't parse
+async fn recv(client: &mut JsonClient) -> Value {
+    let line = tokio::time::timeout(Duration::from_secs(5), client.next())
+        .await
+        .expect("server to answer in time")
+        .expect("connection to stay open")
+        .unwrap();
+    serde_json::from_str(&line).unwrap()
+}
+
+#[tokio::test]
+async fn json_clients_get_json_objects() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().protocol(Protocol::Json).build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = connect(addr).await;
+            let login = recv(&mut a).await;
+            assert_eq!(login["type"], "login");
+            let a_id = login["id"].as_u64().unwrap();
+
+            let mut b = connect(addr).await;
+            let b_id = recv(&mut b).await["id"].as_u64().unwrap();
+            assert_eq!(recv(&mut a).await, json!({ "type": "join", "id": b_id }));
+
+            b.send(r#"{"type":"message","content":"hi there"}"#)
+                .await
+                .unwrap();
+            let msg = recv(&mut a).await;
+            assert_eq!(msg["type"], "message");
+            assert_eq!(msg["seq"], 0);
+            assert_eq!(msg["from"], b_id.to_string());
+            assert_eq!(msg["content"], "hi there");
+            assert!(msg["sent_at"].is_u64());
+
+            // commands are sent as message content too
+            a.send(r#"{"type":"message","content":"/whoami"}"#)
+                .await
+                .unwrap();
+            let me = recv(&mut a).await;
+            assert_eq!(me["type"], "self");
+            assert_eq!(me["id"], a_id);
+
+            a.send("not json").await.unwrap();
+            assert_eq!(
+                recv(&mut a).await,
+                json!({ "type": "err", "reason": "bad json" })
+            );
+        })
+        .await;
+}
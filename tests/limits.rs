@@ -0,0 +1,71 @@
+mod common;
+
+use std::{net::SocketAddr, time::Duration};
+
+use broadcast_server_example::test_util::TestClient;
+use common::{localhost, recv, start};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::{TcpSocket, TcpStream},
+    task::LocalSet,
+};
+
+/// the one line a turned away client is sent before the connection is closed
+async fn turned_away(addr: SocketAddr) -> String {
+    let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+    let mut line = String::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_line(&mut line))
+        .await
+        .expect("server to answer in time")
+        .unwrap();
+
+    let mut rest = String::new();
+    assert_eq!(stream.read_line(&mut rest).await.unwrap(), 0, "got {rest}");
+    line
+}
+
+#[tokio::test]
+async fn clients_over_the_limit_are_told_the_server_is_full() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().max_connections(2).build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            assert_eq!(turned_away(addr).await, "FULL\n");
+
+            // a slot frees up once someone leaves
+            drop(b);
+            assert!(recv(&mut a).await.starts_with("LEFT:"));
+            let c = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", c.id()));
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn hosts_over_their_limit_are_told_too_many() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().max_connections_per_ip(2).build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            assert_eq!(turned_away(addr).await, "TOOMANY\n");
+
+            // other hosts have limits of their own, any address in 127.0.0.0/8 works on loopback
+            let socket = TcpSocket::new_v4().unwrap();
+            socket.bind("127.0.0.2:0".parse().unwrap()).unwrap();
+            let c = TestClient::from_stream(socket.connect(addr).await.unwrap())
+                .await
+                .unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", c.id()));
+        })
+        .await;
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use broadcast_server_example::{server::Message, test_util::TestClient};
+use common::{localhost, start};
+use tokio::task::LocalSet;
+
+#[test]
+fn lines_parse_and_print_the_same() {
+    for line in [
+        "LOGIN:3 v1",
+        "JOIN:4",
+        "LEFT:4",
+        "LEFT:4 idle",
+        "MESSAGE:0:alice 1700000000000 hello there",
+        "MESSAGE:1:3 1700000000000 ",
+        "MESSAGE:2:alice#5 1700000000000 numbered",
+        "HISTORY:7:3 1700000000000 earlier",
+        "DM:alice psst",
+        "MOTD:be nice",
+        "PEERS:",
+        "PEERS:3,alice#bot",
+        "STATS:10 20",
+        "SELF:3 room=global",
+        "SELF:3 nick=alice room=lobby",
+        "OK:nick set",
+        "OK:tag set",
+        "OK:mode observer",
+        "ACK:2",
+        "OK:compress on",
+        "OK:compress off",
+        "OK:joined lobby",
+        "OK:hello v1",
+        "OK:hello v1 compress rooms",
+        "OK:maxlen 1024",
+        "ERR:rate limited",
+        "FULL",
+        "TOOMANY",
+        "PING",
+        "KICKED:by admin",
+        "BYE:server shutting down",
+        "SYSTEM:maintenance at noon",
+    ] {
+        let msg: Message = line.parse().unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(msg.to_string(), line, "{msg:?}");
+    }
+
+    for line in [
+        "",
+        "HELLO",
+        "LOGIN:x v1",
+        "STATS:1",
+        "MESSAGE:0:3 soon",
+        "MESSAGE:0:3#x 1700000000000 hi",
+    ] {
+        assert!(line.parse::<Message>().is_err(), "{line:?} parsed");
+    }
+}
+
+#[tokio::test]
+async fn what_the_server_sends_parses() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(
+                a.recv_message().await.unwrap(),
+                Some(Message::Join { id: b.id() })
+            );
+
+            b.send_line("hi").await.unwrap();
+            let Some(Message::Chat { from, content, .. }) = a.recv_message().await.unwrap() else {
+                panic!("expected a chat message");
+            };
+            assert_eq!(from, b.id().to_string());
+            assert_eq!(content, "hi");
+
+            a.send_line("/whoami").await.unwrap();
+            assert_eq!(
+                a.recv_message().await.unwrap(),
+                Some(Message::Identity {
+                    id: a.id(),
+                    nick: None,
+                    room: "global".into()
+                })
+            );
+        })
+        .await;
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use broadcast_server_example::{config::RateLimit, server::Server, test_util::TestClient};
+use common::{localhost, recv, QUIET_PERIOD};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc,
+    task::LocalSet,
+};
+
+async fn scrape(addr: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\n\r\n")
+        .await
+        .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn drops_and_disconnects_are_counted_by_reason() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .metrics_addr("127.0.0.1:0".parse().unwrap())
+        .echo_self(true)
+        .rate_limit(RateLimit {
+            per_second: 0.001,
+            burst: 1,
+        })
+        .build();
+    let server = Server::bind(config).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let metrics_addr = server.metrics_addr().unwrap();
+
+    let (_, announcements) = mpsc::channel(1);
+    local.spawn_local(server.run(announcements, std::future::pending()));
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+
+            a.send_line("hello").await.unwrap();
+            assert!(recv(&mut a).await.starts_with("MESSAGE:"));
+            a.send_line("again").await.unwrap();
+            assert_eq!(recv(&mut a).await, "ERR:rate limited");
+
+            drop(a);
+            tokio::time::sleep(QUIET_PERIOD).await;
+
+            let response = scrape(metrics_addr).await;
+            assert!(
+                response.contains("broadcast_dropped_total{reason=\"rate_limit\"} 1\n"),
+                "{response}"
+            );
+            assert!(
+                response.contains("broadcast_disconnects_total{reason=\"closed\"} 1\n"),
+                "{response}"
+            );
+        })
+        .await;
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use std::{net::SocketAddr, time::Duration};
+
+use broadcast_server_example::config::Prefixes;
+use common::{localhost, start};
+use futures::{SinkExt, StreamExt};
+use tokio::{net::TcpStream, task::LocalSet};
+use tokio_util::codec::{Framed, LinesCodec};
+
+async fn connect(addr: SocketAddr) -> Framed<TcpStream, LinesCodec> {
+    Framed::new(TcpStream::connect(addr).await.unwrap(), LinesCodec::new())
+}
+
+async fn recv(client: &mut Framed<TcpStream, LinesCodec>) -> String {
+    tokio::time::timeout(Duration::from_secs(5), client.next())
+        .await
+        .expect("server to answer in time")
+        .expect("connection to stay open")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn greetings_and_messages_use_the_configured_prefixes() {
+    let prefixes = Prefixes {
+        login: "HELLO ".to_owned(),
+        message: "MSG ".to_owned(),
+    };
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().prefixes(prefixes).build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = connect(addr).await;
+            assert!(recv(&mut a).await.starts_with("HELLO "));
+            let mut b = connect(addr).await;
+            let login = recv(&mut b).await;
+            let b_id = login
+                .strip_prefix("HELLO ")
+                .and_then(|rest| rest.split(' ').next())
+                .unwrap()
+                .to_owned();
+
+            // fixed lines keep their usual prefixes
+            assert_eq!(recv(&mut a).await, format!("JOIN:{b_id}"));
+
+            b.send("prefixed").await.unwrap();
+            let line = recv(&mut a).await;
+            assert!(line.starts_with(&format!("MSG 0:{b_id} ")), "{line}");
+            assert!(line.ends_with(" prefixed"), "{line}");
+        })
+        .await;
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use broadcast_server_example::{config::ClientIds, server::Server, test_util::TestClient};
+use common::{localhost, start};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc,
+    task::LocalSet,
+};
+
+/// connects as a load balancer would, sending `header` first
+async fn connect_through(addr: SocketAddr, header: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(header.as_bytes()).await.unwrap();
+    stream
+}
+
+/// true once the server has closed the connection without a word,
+/// closing with part of a header unread resets it instead
+async fn closed_silently(mut stream: TcpStream) -> bool {
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf).await.is_err() || buf.is_empty()
+}
+
+#[tokio::test]
+async fn clients_are_known_by_the_address_the_balancer_passes_on() {
+    let config = localhost()
+        .proxy_protocol(true)
+        .client_ids(ClientIds::Port)
+        .build();
+    let server = Server::bind(config).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let roster = server.roster();
+
+    let (_, announcements) = mpsc::channel(1);
+    let local = LocalSet::new();
+    local.spawn_local(server.run(announcements, std::future::pending()));
+
+    local
+        .run_until(async move {
+            let v4 = connect_through(addr, "PROXY TCP4 203.0.113.7 10.0.0.1 4242 8888\r\n").await;
+            let a = TestClient::from_stream(v4).await.unwrap();
+            assert_eq!(a.id().to_string(), "4242");
+
+            let v6 =
+                connect_through(addr, "PROXY TCP6 2001:db8::1 2001:db8::2 4343 8888\r\n").await;
+            let b = TestClient::from_stream(v6).await.unwrap();
+
+            let mut peers = roster.snapshot().await.unwrap();
+            peers.sort_by_key(|peer| peer.id);
+            assert_eq!(peers[0].id, a.id());
+            assert_eq!(peers[0].addr, Some("203.0.113.7:4242".parse().unwrap()));
+            assert_eq!(peers[1].id, b.id());
+            assert_eq!(peers[1].addr, Some("[2001:db8::1]:4343".parse().unwrap()));
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn unknown_sources_keep_the_balancer_address() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().proxy_protocol(true).build()).await;
+
+    local
+        .run_until(async move {
+            let stream = connect_through(addr, "PROXY UNKNOWN\r\n").await;
+            TestClient::from_stream(stream).await.unwrap();
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn malformed_headers_are_refused() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().proxy_protocol(true).build()).await;
+
+    local
+        .run_until(async move {
+            for header in [
+                "hello\n",
+                "PROXY TCP4 203.0.113.7 10.0.0.1 4242\r\n",
+                "PROXY TCP4 2001:db8::1 10.0.0.1 4242 8888\r\n",
+                "PROXY TCP4 203.0.113.7 10.0.0.1 99999 8888\r\n",
+                "PROXY UDP4 203.0.113.7 10.0.0.1 4242 8888\r\n",
+                "PROXY TCP4 203.0.113.7 10.0.0.1 4242 8888\n",
+            ] {
+                let stream = connect_through(addr, header).await;
+                assert!(closed_silently(stream).await, "{header:?} was let through");
+            }
+
+            let stream = connect_through(addr, &format!("PROXY {}", "x".repeat(200))).await;
+            assert!(closed_silently(stream).await);
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn the_deny_list_applies_to_the_client_behind_the_balancer() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .proxy_protocol(true)
+        .deny(["203.0.113.0/24".parse().unwrap()])
+        .build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let denied =
+                connect_through(addr, "PROXY TCP4 203.0.113.7 10.0.0.1 4242 8888\r\n").await;
+            assert!(closed_silently(denied).await);
+
+            let allowed =
+                connect_through(addr, "PROXY TCP4 198.51.100.7 10.0.0.1 4242 8888\r\n").await;
+            TestClient::from_stream(allowed).await.unwrap();
+        })
+        .await;
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use std::time::Duration;
+
+use broadcast_server_example::{config::ServerConfig, test_util::TestClient};
+use common::{recv, start, QUIET_PERIOD};
+use tokio::{io::AsyncReadExt, net::TcpStream, task::LocalSet};
+
+// in a test binary of its own, SIGHUP reaches every server in the process
+#[tokio::test]
+async fn sighup_reloads_the_deny_list() {
+    let dir = std::env::temp_dir().join(format!("reload-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("server.toml");
+    std::fs::write(&path, "bind = [\"127.0.0.1:0\"]\n").unwrap();
+
+    let local = LocalSet::new();
+    let mut config = ServerConfig::from_file(&path).unwrap();
+    config.reload_on_sighup = true;
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+
+            std::fs::write(
+                &path,
+                "bind = [\"127.0.0.1:0\"]\ndeny = [\"127.0.0.0/8\"]\n",
+            )
+            .unwrap();
+            // SAFETY: signalling ourselves, the server has a handler installed by now
+            assert_eq!(unsafe { libc::kill(libc::getpid(), libc::SIGHUP) }, 0);
+            tokio::time::sleep(QUIET_PERIOD).await;
+
+            // refused connections are closed without a greeting
+            let mut b = TcpStream::connect(addr).await.unwrap();
+            let mut buf = Vec::new();
+            let read = tokio::time::timeout(Duration::from_secs(5), b.read_to_end(&mut buf))
+                .await
+                .unwrap();
+            assert!(read.is_err() || buf.is_empty(), "got {buf:?}");
+
+            // already connected clients carry on
+            a.send_line("/whoami").await.unwrap();
+            assert!(recv(&mut a).await.starts_with("SELF:"));
+        })
+        .await;
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
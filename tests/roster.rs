@@ -0,0 +1,44 @@
+mod common;
+
+use broadcast_server_example::{server::Server, test_util::TestClient};
+use common::{localhost, recv};
+use tokio::{sync::mpsc, task::LocalSet};
+
+#[tokio::test]
+async fn roster_lists_who_is_connected() {
+    let server = Server::bind(localhost().build()).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let roster = server.roster();
+
+    let (_, announcements) = mpsc::channel(1);
+    let local = LocalSet::new();
+    local.spawn_local(server.run(announcements, std::future::pending()));
+
+    local
+        .run_until(async move {
+            assert_eq!(roster.snapshot().await.unwrap(), []);
+
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+
+            a.send_line("/nick alice").await.unwrap();
+            assert_eq!(recv(&mut a).await, "OK:nick set");
+            a.send_line("/join lobby").await.unwrap();
+            assert_eq!(recv(&mut a).await, "OK:joined lobby");
+
+            let mut peers = roster.snapshot().await.unwrap();
+            peers.sort_by_key(|peer| peer.id);
+            assert_eq!(peers.len(), 2);
+
+            assert_eq!(peers[0].id, a.id());
+            assert_eq!(peers[0].nick.as_deref(), Some("alice"));
+            assert_eq!(peers[0].room, "lobby");
+
+            assert_eq!(peers[1].id, b.id());
+            assert_eq!(peers[1].nick, None);
+            assert_eq!(peers[1].room, "global");
+            assert!(peers[1].addr.is_some());
+        })
+        .await;
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use broadcast_server_example::config::Tls;
+use common::{localhost, start};
+use futures::{SinkExt, StreamExt};
+use tokio::{net::TcpStream, task::LocalSet};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{
+        pki_types::{pem::PemObject, CertificateDer, ServerName},
+        ClientConfig, RootCertStore,
+    },
+    TlsConnector,
+};
+use tokio_util::codec::{Framed, LinesCodec};
+
+/// `cert.pem` is self signed for `localhost` and valid until 2126, `key.pem` is its key
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// connects trusting nothing but the fixture certificate
+async fn connect(addr: SocketAddr) -> Framed<TlsStream<TcpStream>, LinesCodec> {
+    let mut roots = RootCertStore::empty();
+    roots
+        .add(CertificateDer::from_pem_file(fixture("cert.pem")).unwrap())
+        .unwrap();
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+        .unwrap();
+    Framed::new(stream, LinesCodec::new())
+}
+
+async fn recv(client: &mut Framed<TlsStream<TcpStream>, LinesCodec>) -> String {
+    tokio::time::timeout(Duration::from_secs(5), client.next())
+        .await
+        .expect("server to answer in time")
+        .expect("connection to stay open")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn clients_talk_over_tls() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .tls(Tls {
+            cert: fixture("cert.pem"),
+            key: fixture("key.pem"),
+        })
+        .build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut a = connect(addr).await;
+            assert!(recv(&mut a).await.starts_with("LOGIN:"));
+            let mut b = connect(addr).await;
+            let login = recv(&mut b).await;
+            let b_id = login
+                .strip_prefix("LOGIN:")
+                .and_then(|rest| rest.split(' ').next())
+                .unwrap()
+                .to_owned();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{b_id}"));
+
+            b.send("over tls").await.unwrap();
+            let line = recv(&mut a).await;
+            assert!(line.starts_with(&format!("MESSAGE:0:{b_id} ")), "{line}");
+            assert!(line.ends_with(" over tls"), "{line}");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn plain_clients_are_not_greeted() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .tls(Tls {
+            cert: fixture("cert.pem"),
+            key: fixture("key.pem"),
+        })
+        .build();
+    let addr = start(&local, config).await;
+
+    local
+        .run_until(async move {
+            let mut plain = Framed::new(TcpStream::connect(addr).await.unwrap(), LinesCodec::new());
+            plain.send("hello").await.unwrap();
+
+            // the handshake fails, at most a tls alert comes back before the connection is dropped
+            let next = tokio::time::timeout(Duration::from_secs(5), plain.next())
+                .await
+                .expect("server to close the connection in time");
+            assert!(
+                !matches!(&next, Some(Ok(line)) if line.starts_with("LOGIN:")),
+                "got {next:?}"
+            );
+        })
+        .await;
+}
@@ -0,0 +1,184 @@
+mod common;
+
+use std::time::Duration;
+
+use broadcast_server_example::{
+    config::{Dedup, Filtered, RateLimit},
+    server::Server,
+    test_util::TestClient,
+};
+use common::{localhost, recv, QUIET_PERIOD};
+use tokio::{net::UdpSocket, sync::mpsc, task::LocalSet};
+
+#[tokio::test]
+async fn datagrams_reach_tcp_clients_and_other_udp_peers() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .udp_bind("127.0.0.1:0".parse().unwrap())
+        .udp_peer_ttl(Duration::from_secs(60))
+        .build();
+    let server = Server::bind(config).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let udp_addr = server.udp_addr().unwrap();
+
+    let (_, announcements) = mpsc::channel(1);
+    local.spawn_local(server.run(announcements, std::future::pending()));
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+
+            let sensor = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            // a peer is only relayed to once it has been heard from
+            listener.send_to(b"here", udp_addr).await.unwrap();
+            let line = recv(&mut a).await;
+            let prefix = format!("MESSAGE:0:{} ", listener.local_addr().unwrap());
+            assert!(line.starts_with(&prefix), "{line}");
+            assert!(line.ends_with(" here"), "{line}");
+
+            sensor.send_to(b"21.5\r\n", udp_addr).await.unwrap();
+            let line = recv(&mut a).await;
+            let prefix = format!("MESSAGE:1:{} ", sensor.local_addr().unwrap());
+            assert!(line.starts_with(&prefix), "{line}");
+            assert!(line.ends_with(" 21.5"), "{line}");
+
+            let mut buf = [0; 128];
+            let (len, from) = tokio::time::timeout(QUIET_PERIOD, listener.recv_from(&mut buf))
+                .await
+                .expect("the datagram to be relayed")
+                .unwrap();
+            assert_eq!(from, udp_addr);
+            assert_eq!(buf[..len], *line.as_bytes());
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn datagrams_over_the_length_limit_are_dropped() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .max_line_length(8)
+        .udp_bind("127.0.0.1:0".parse().unwrap())
+        .build();
+    let server = Server::bind(config).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let udp_addr = server.udp_addr().unwrap();
+
+    let (_, announcements) = mpsc::channel(1);
+    local.spawn_local(server.run(announcements, std::future::pending()));
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let sensor = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+            sensor.send_to(b"far too long", udp_addr).await.unwrap();
+            sensor.send_to(b"short", udp_addr).await.unwrap();
+
+            let line = recv(&mut a).await;
+            assert!(line.ends_with(" short"), "{line}");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn datagrams_are_held_to_the_same_limits_as_messages() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .max_message_bytes(8)
+        .rate_limit(RateLimit {
+            per_second: 0.001,
+            burst: 5,
+        })
+        .dedup(Dedup {
+            window: Duration::from_secs(60),
+            cache_size: 8,
+        })
+        .filter(|msg| match msg {
+            "spam" => Filtered::Reject,
+            _ => Filtered::Pass,
+        })
+        .udp_bind("127.0.0.1:0".parse().unwrap())
+        .build();
+    let server = Server::bind(config).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let udp_addr = server.udp_addr().unwrap();
+
+    let (_, announcements) = mpsc::channel(1);
+    local.spawn_local(server.run(announcements, std::future::pending()));
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let sensor = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+            for datagram in ["hello", "hello", "spam", "far too long", "bye", "over"] {
+                sensor.send_to(datagram.as_bytes(), udp_addr).await.unwrap();
+            }
+
+            assert!(recv(&mut a).await.ends_with(" hello"));
+            // the repeat, the filtered one and the long one still count against the rate limit
+            assert!(recv(&mut a).await.ends_with(" bye"));
+            let leaked = tokio::time::timeout(QUIET_PERIOD, a.recv_line()).await;
+            assert!(leaked.is_err(), "got {leaked:?}");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn datagrams_from_denied_addresses_are_dropped() {
+    let local = LocalSet::new();
+    let config = localhost()
+        .deny(["127.0.0.2/32".parse().unwrap()])
+        .udp_bind("127.0.0.1:0".parse().unwrap())
+        .build();
+    let server = Server::bind(config).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let udp_addr = server.udp_addr().unwrap();
+
+    let (_, announcements) = mpsc::channel(1);
+    local.spawn_local(server.run(announcements, std::future::pending()));
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let denied = UdpSocket::bind("127.0.0.2:0").await.unwrap();
+            let allowed = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+            denied.send_to(b"denied", udp_addr).await.unwrap();
+            allowed.send_to(b"allowed", udp_addr).await.unwrap();
+
+            let line = recv(&mut a).await;
+            assert!(line.ends_with(" allowed"), "{line}");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn datagrams_that_arent_text_are_dropped_under_line_framing() {
+    let local = LocalSet::new();
+    let config = localhost().udp_bind("127.0.0.1:0".parse().unwrap()).build();
+    let server = Server::bind(config).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let udp_addr = server.udp_addr().unwrap();
+
+    let (_, announcements) = mpsc::channel(1);
+    local.spawn_local(server.run(announcements, std::future::pending()));
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut b = TestClient::connect(addr).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{}", b.id()));
+            let sensor = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+            sensor.send_to(b"hi \xff", udp_addr).await.unwrap();
+            sensor.send_to(b"still here", udp_addr).await.unwrap();
+
+            // neither client was cut off by the bad one
+            assert!(recv(&mut a).await.ends_with(" still here"));
+            assert!(recv(&mut b).await.ends_with(" still here"));
+        })
+        .await;
+}
@@ -0,0 +1,72 @@
+use std::{path::Path, time::Duration};
+
+use broadcast_server_example::{config::ServerConfig, server::Server};
+use futures::{SinkExt, StreamExt};
+use tokio::{
+    net::UnixStream,
+    sync::{mpsc, oneshot},
+    task::LocalSet,
+};
+use tokio_util::codec::{Framed, LinesCodec};
+
+async fn connect(path: &Path) -> Framed<UnixStream, LinesCodec> {
+    Framed::new(UnixStream::connect(path).await.unwrap(), LinesCodec::new())
+}
+
+async fn recv(client: &mut Framed<UnixStream, LinesCodec>) -> String {
+    tokio::time::timeout(Duration::from_secs(5), client.next())
+        .await
+        .expect("server to answer in time")
+        .expect("connection to stay open")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn clients_talk_over_a_unix_socket() {
+    let dir = std::env::temp_dir().join(format!("unix-socket-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("server.sock");
+
+    let server = Server::bind(ServerConfig::builder().unix_socket(&path).build())
+        .await
+        .unwrap();
+    assert_eq!(server.local_addr(), None);
+
+    let (_, announcements) = mpsc::channel(1);
+    let (stop, stopped) = oneshot::channel::<()>();
+    let local = LocalSet::new();
+    let running = local.spawn_local(server.run(announcements, async {
+        let _ = stopped.await;
+    }));
+
+    local
+        .run_until(async {
+            let mut a = connect(&path).await;
+            assert!(recv(&mut a).await.starts_with("LOGIN:"));
+            let mut b = connect(&path).await;
+            let login = recv(&mut b).await;
+            let b_id = login
+                .strip_prefix("LOGIN:")
+                .and_then(|rest| rest.split(' ').next())
+                .unwrap()
+                .to_owned();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{b_id}"));
+
+            b.send("over a unix socket").await.unwrap();
+            let line = recv(&mut a).await;
+            assert!(line.starts_with(&format!("MESSAGE:0:{b_id} ")), "{line}");
+            assert!(line.ends_with(" over a unix socket"), "{line}");
+
+            // the socket file is cleaned up once the server stops
+            stop.send(()).unwrap();
+            tokio::time::timeout(Duration::from_secs(10), running)
+                .await
+                .expect("server to stop in time")
+                .unwrap()
+                .unwrap();
+            assert!(!path.exists());
+        })
+        .await;
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
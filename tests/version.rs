@@ -0,0 +1,80 @@
+mod common;
+
+use broadcast_server_example::test_util::TestClient;
+use common::{localhost, recv, start};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    task::LocalSet,
+};
+
+#[tokio::test]
+async fn greeting_carries_the_version() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().build()).await;
+
+    local
+        .run_until(async move {
+            let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+
+            let (_, version) = line.trim_end().split_once(' ').unwrap();
+            assert!(line.starts_with("LOGIN:"), "{line}");
+            assert_eq!(version, "v1");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn hello_limits_clients_to_what_they_named() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+
+            // json isn't on offer from a plain server and nobody knows what teleport is
+            a.send_line("HELLO v1 rooms json teleport").await.unwrap();
+            assert_eq!(recv(&mut a).await, "OK:hello v1 rooms");
+
+            a.send_line("/join lobby").await.unwrap();
+            assert_eq!(recv(&mut a).await, "OK:joined lobby");
+
+            a.send_line("COMPRESS on").await.unwrap();
+            assert_eq!(recv(&mut a).await, "ERR:not negotiated");
+
+            // everyone else still gets everything
+            let mut b = TestClient::connect(addr).await.unwrap();
+            b.send_line("COMPRESS off").await.unwrap();
+            assert_eq!(recv(&mut b).await, "OK:compress off");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn unknown_versions_are_turned_away() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = TestClient::connect(addr).await.unwrap();
+            let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let id = line.trim_end().split_once(' ').unwrap().0["LOGIN:".len()..].to_owned();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{id}"));
+
+            stream.write_all(b"HELLO v2\n").await.unwrap();
+            line.clear();
+            stream.read_line(&mut line).await.unwrap();
+            assert_eq!(line, "ERR:unsupported version\n");
+
+            line.clear();
+            assert_eq!(stream.read_line(&mut line).await.unwrap(), 0);
+            assert_eq!(recv(&mut a).await, format!("LEFT:{id}"));
+        })
+        .await;
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use std::{net::SocketAddr, time::Duration};
+
+use common::{localhost, start};
+use futures::{SinkExt, StreamExt};
+use tokio::{net::TcpStream, task::LocalSet};
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+
+async fn connect(addr: SocketAddr) -> WebSocketStream<TcpStream> {
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (ws, _) = tokio_tungstenite::client_async(format!("ws://{addr}/"), stream)
+        .await
+        .unwrap();
+    ws
+}
+
+/// the next text message, panicking if it doesn't arrive or the connection closes
+async fn recv(ws: &mut WebSocketStream<TcpStream>) -> String {
+    let msg = tokio::time::timeout(Duration::from_secs(5), ws.next())
+        .await
+        .expect("server to answer in time")
+        .expect("connection to stay open")
+        .unwrap();
+    match msg {
+        Message::Text(text) => text.to_string(),
+        msg => panic!("expected text, got {msg:?}"),
+    }
+}
+
+#[tokio::test]
+async fn websocket_clients_get_a_message_per_frame() {
+    let local = LocalSet::new();
+    let addr = start(&local, localhost().websocket(true).build()).await;
+
+    local
+        .run_until(async move {
+            let mut a = connect(addr).await;
+            assert!(recv(&mut a).await.starts_with("LOGIN:"));
+            let mut b = connect(addr).await;
+            let login = recv(&mut b).await;
+            let b_id = login
+                .strip_prefix("LOGIN:")
+                .and_then(|rest| rest.split(' ').next())
+                .unwrap()
+                .to_owned();
+            assert_eq!(recv(&mut a).await, format!("JOIN:{b_id}"));
+
+            // a frame is a message whatever is in it, newlines included
+            b.send(Message::text("two\nlines")).await.unwrap();
+            let msg = recv(&mut a).await;
+            assert!(msg.starts_with(&format!("MESSAGE:0:{b_id} ")), "{msg}");
+            assert!(msg.ends_with(" two\nlines"), "{msg:?}");
+
+            b.send(Message::Close(None)).await.unwrap();
+            assert_eq!(recv(&mut a).await, format!("LEFT:{b_id}"));
+        })
+        .await;
+}